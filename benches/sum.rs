@@ -2,10 +2,19 @@
 
 extern crate test;
 use test::{black_box, Bencher};
+use vm_perf::closure_stack_continuations::{Guarded, Presized};
+use vm_perf::optimize::Options;
 use vm_perf::{
-    Bytecode, BytecodeClosures, ClosureContinuations, ClosureStackContinuations, Closures, Expr,
-    RegisterClosures, StackClosures, TapeClosures, TapeContinuations, Vm, Walker,
+    optimize, optimize_with, specialize, Bytecode, BytecodeClosures, ClosureContinuations,
+    ClosureStackContinuations, ClosureTailCalls, Closures, Expr, RegisterBytecode,
+    RegisterClosures, ReplicatedBytecode, SimdClosures, Ssa, StackCachedBytecode, StackClosures,
+    SuperBytecode, TapeClosures, TapeContinuations, TapeTailCalls, ThreadedBytecode, Tiered,
+    TracingBytecode, Vm, Walker,
 };
+#[cfg(unix)]
+use vm_perf::{CAot, RustcAot};
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+use vm_perf::{CopyAndPatch, Jit};
 
 fn create_expr() -> Expr {
     // let mut total = 0;
@@ -39,6 +48,121 @@ fn create_expr() -> Expr {
     )
 }
 
+// The same program, with the kind of redundancy a naive front end leaves behind, for the optimiser to remove:
+// let mut total = 0;
+// let unused = args[0] + 1;
+// let mut count = args[0];
+// while count > 0 {
+//     total = total + (args[1] + 0);
+//     while 0 { total = 0; }
+//     count = count + (-2 + 1);
+// }
+// total
+fn create_redundant_expr() -> Expr {
+    Expr::Let(
+        Box::new(Expr::Litr(0)), // total
+        Box::new(Expr::Then(
+            Box::new(Expr::Let(
+                Box::new(Expr::Add(Box::new(Expr::Arg(0)), Box::new(Expr::Litr(1)))), // unused
+                Box::new(Expr::Let(
+                    Box::new(Expr::Arg(0)), // counter
+                    Box::new(Expr::While(
+                        Box::new(Expr::Get(0)),
+                        Box::new(Expr::Then(
+                            Box::new(Expr::Set(
+                                2,
+                                Box::new(Expr::Add(
+                                    Box::new(Expr::Get(2)),
+                                    Box::new(Expr::Add(
+                                        Box::new(Expr::Arg(1)),
+                                        Box::new(Expr::Litr(0)),
+                                    )),
+                                )),
+                            )),
+                            Box::new(Expr::Then(
+                                Box::new(Expr::While(
+                                    Box::new(Expr::Litr(0)),
+                                    Box::new(Expr::Set(2, Box::new(Expr::Litr(0)))),
+                                )),
+                                Box::new(Expr::Set(
+                                    0,
+                                    Box::new(Expr::Add(
+                                        Box::new(Expr::Get(0)),
+                                        Box::new(Expr::Add(
+                                            Box::new(Expr::Litr(-2)),
+                                            Box::new(Expr::Litr(1)),
+                                        )),
+                                    )),
+                                )),
+                            )),
+                        )),
+                    )),
+                )),
+            )),
+            Box::new(Expr::Get(0)), // total
+        )),
+    )
+}
+
+// A program with more locals than most techniques have registers, used with very different frequencies:
+// let mut total = 0;
+// let mut count = args[0];
+// let mut x1 = 0; ...; let mut x6 = 0;
+// let cold1 = args[1];
+// let cold2 = 1;
+// while count > 0 {
+//     x1 = count + args[1];
+//     x2 = x1 + 2; ...; x6 = x5 + 6;
+//     total = total + x6;
+//     count = count - 1;
+// }
+// total + cold1 + cold2
+fn create_many_locals_expr() -> Expr {
+    const LOCALS: usize = 10;
+    // Refer to locals by the order they're declared in, rather than by offset
+    let get = |slot: usize| Box::new(Expr::Get(LOCALS - 1 - slot));
+    let set = |slot: usize, rhs| Box::new(Expr::Set(LOCALS - 1 - slot, rhs));
+    let add = |x, y| Box::new(Expr::Add(x, y));
+    let then = |a, b| Box::new(Expr::Then(a, b));
+
+    let mut body = then(
+        set(0, add(get(0), get(7))),
+        set(1, add(get(1), Box::new(Expr::Litr(-1)))),
+    );
+    for x in (2..=7).rev() {
+        let rhs = match x {
+            2 => add(get(1), Box::new(Expr::Arg(1))),
+            _ => add(get(x - 1), Box::new(Expr::Litr(x as i64 - 1))),
+        };
+        body = then(set(x, rhs), body);
+    }
+    let result = add(add(get(0), get(8)), get(9));
+    let inits = [
+        Expr::Litr(0),
+        Expr::Arg(0),
+        Expr::Litr(0),
+        Expr::Litr(0),
+        Expr::Litr(0),
+        Expr::Litr(0),
+        Expr::Litr(0),
+        Expr::Litr(0),
+        Expr::Arg(1),
+        Expr::Litr(1),
+    ];
+    let expr = then(Box::new(Expr::While(get(1), body)), result);
+    *inits
+        .into_iter()
+        .rev()
+        .fold(expr, |then, init| Box::new(Expr::Let(Box::new(init), then)))
+}
+
+// Many copies of the same program, to measure compile throughput for larger inputs
+fn create_large_expr() -> Expr {
+    (0..31).fold(create_expr(), |b, _| {
+        Expr::Then(Box::new(create_expr()), Box::new(b))
+    })
+}
+
 #[inline(never)]
 unsafe fn rust_impl(args: &[i64]) -> i64 {
     // Lots of silly stuff to force the compiler skip basically all attempts at optimisation
@@ -56,8 +180,8 @@ unsafe fn rust_impl_opt(args: &[i64]) -> i64 {
     let mut total = 0;
     let mut count = *args.get_unchecked(0);
     while count > 0 {
-        total = total + *args.get_unchecked(1);
-        count = count + -1;
+        total += *args.get_unchecked(1);
+        count -= 1;
     }
     total
 }
@@ -70,6 +194,15 @@ fn answer() -> i64 {
     10000 * 13
 }
 
+// Few enough iterations that per-call setup dominates
+fn create_small_args() -> &'static [i64] {
+    &[2, 13]
+}
+
+fn small_answer() -> i64 {
+    2 * 13
+}
+
 fn bench_compile<V: Vm>(b: &mut Bencher) {
     let expr = black_box(create_expr());
 
@@ -78,6 +211,14 @@ fn bench_compile<V: Vm>(b: &mut Bencher) {
     });
 }
 
+fn bench_compile_large<V: Vm>(b: &mut Bencher) {
+    let expr = black_box(create_large_expr());
+
+    b.iter(move || {
+        black_box(V::compile(&expr));
+    });
+}
+
 fn bench_execute<V: Vm>(b: &mut Bencher) {
     let expr = create_expr();
 
@@ -91,6 +232,187 @@ fn bench_execute<V: Vm>(b: &mut Bencher) {
     });
 }
 
+fn bench_execute_small<V: Vm>(b: &mut Bencher) {
+    let expr = create_expr();
+
+    let program = black_box(V::compile(&expr));
+
+    let args = black_box(create_small_args());
+
+    b.iter(move || {
+        let res = unsafe { black_box(V::execute(&program, args)) };
+        assert_eq!(res, small_answer());
+    });
+}
+
+fn bench_execute_small_reuse<V: Vm>(b: &mut Bencher) {
+    let expr = create_expr();
+
+    let program = black_box(V::compile(&expr));
+
+    let args = black_box(create_small_args());
+
+    let mut ctx = V::new_context();
+
+    b.iter(move || {
+        let res = unsafe { black_box(V::execute_in(&mut ctx, &program, args)) };
+        assert_eq!(res, small_answer());
+    });
+}
+
+// Many small argument sets, like a predicate evaluated over rows of a table
+fn create_batch_args() -> Vec<[i64; 2]> {
+    (0..1000).map(|i| [i % 8, i]).collect()
+}
+
+fn bench_execute_batch<V: Vm>(b: &mut Bencher) {
+    let expr = create_expr();
+
+    let program = black_box(V::compile(&expr));
+
+    let batch = black_box(create_batch_args());
+    let args = batch.iter().map(|args| &args[..]).collect::<Vec<_>>();
+    let mut out = vec![0; args.len()];
+
+    b.iter(|| {
+        unsafe { V::execute_batch(&program, &args, &mut out) };
+        black_box(&out);
+    });
+    for (args, res) in batch.iter().zip(&out) {
+        assert_eq!(*res, args[0] * args[1]);
+    }
+}
+
+fn bench_execute_per_call<V: Vm>(b: &mut Bencher) {
+    let expr = create_expr();
+
+    let program = black_box(V::compile(&expr));
+
+    let batch = black_box(create_batch_args());
+    let mut out = vec![0; batch.len()];
+
+    b.iter(|| {
+        for (args, out) in batch.iter().zip(&mut out) {
+            *out = unsafe { V::execute(&program, args) };
+        }
+        black_box(&out);
+    });
+    for (args, res) in batch.iter().zip(&out) {
+        assert_eq!(*res, args[0] * args[1]);
+    }
+}
+
+// The same program, specialised on `args[1]` being known ahead of time (only the trip count varies)
+fn bench_execute_specialized<V: Vm>(b: &mut Bencher) {
+    let args = black_box(create_args());
+    let expr = specialize(&create_expr(), &[None, Some(args[1])]);
+
+    let program = black_box(V::compile(&expr));
+
+    b.iter(move || {
+        let res = unsafe { black_box(V::execute(&program, args)) };
+        assert_eq!(res, answer());
+    });
+}
+
+fn bench_execute_redundant<V: Vm>(b: &mut Bencher) {
+    let expr = create_redundant_expr();
+
+    let program = black_box(V::compile(&expr));
+
+    let args = black_box(create_args());
+
+    b.iter(move || {
+        let res = unsafe { black_box(V::execute(&program, args)) };
+        assert_eq!(res, answer());
+    });
+}
+
+// The redundant program, run through the optimiser first. Loops are kept, so this still measures dispatch.
+fn bench_execute_optimized<V: Vm>(b: &mut Bencher) {
+    let options = Options {
+        closed_form_loops: false,
+    };
+    let expr = optimize_with(&create_redundant_expr(), options);
+
+    let program = black_box(V::compile(&expr));
+
+    let args = black_box(create_args());
+
+    b.iter(move || {
+        let res = unsafe { black_box(V::execute(&program, args)) };
+        assert_eq!(res, answer());
+    });
+}
+
+// The main program, with its loop replaced by closed-form arithmetic
+fn bench_execute_closed_form<V: Vm>(b: &mut Bencher) {
+    let expr = optimize(&create_expr());
+
+    let program = black_box(V::compile(&expr));
+
+    let args = black_box(create_args());
+
+    b.iter(move || {
+        let res = unsafe { black_box(V::execute(&program, args)) };
+        assert_eq!(res, answer());
+    });
+}
+
+fn bench_execute_many_locals<V: Vm>(b: &mut Bencher) {
+    let expr = create_many_locals_expr();
+    let answer = unsafe { Walker::execute(&Walker::compile(&expr), create_args()) };
+
+    let program = black_box(V::compile(&expr));
+
+    let args = black_box(create_args());
+
+    b.iter(move || {
+        let res = unsafe { black_box(V::execute(&program, args)) };
+        assert_eq!(res, answer);
+    });
+}
+
+// Compile a program and execute it `CALLS` times, like a query that gets run some number of times over its lifetime. As
+// `CALLS` grows, compile time matters less and execution speed more.
+fn bench_calls<V: Vm, const CALLS: usize>(b: &mut Bencher) {
+    let expr = create_expr();
+    let args = black_box(create_small_args());
+
+    b.iter(|| {
+        let program = V::compile(&expr);
+        let mut ctx = V::new_context();
+        for _ in 0..CALLS {
+            let res = unsafe { V::execute_in(&mut ctx, &program, args) };
+            assert_eq!(res, small_answer());
+        }
+    });
+}
+
+// Run one shared program on several threads at once, each with different arguments
+fn bench_execute_parallel<V: Vm>(b: &mut Bencher) {
+    const THREADS: i64 = 4;
+
+    let expr = create_expr();
+
+    let program = black_box(V::compile(&expr));
+
+    let args = black_box(create_args());
+
+    b.iter(|| {
+        std::thread::scope(|s| {
+            for i in 0..THREADS {
+                let program = &program;
+                s.spawn(move || {
+                    let args = [args[0], args[1] + i];
+                    let res = unsafe { black_box(V::execute(program, &args)) };
+                    assert_eq!(res, args[0] * args[1]);
+                });
+            }
+        });
+    });
+}
+
 // AST walker
 #[bench]
 fn walker_compile(b: &mut Bencher) {
@@ -100,6 +422,42 @@ fn walker_compile(b: &mut Bencher) {
 fn walker_execute(b: &mut Bencher) {
     bench_execute::<Walker>(b)
 }
+#[bench]
+fn walker_execute_small(b: &mut Bencher) {
+    bench_execute_small::<Walker>(b)
+}
+#[bench]
+fn walker_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<Walker>(b)
+}
+#[bench]
+fn walker_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<Walker>(b)
+}
+#[bench]
+fn walker_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<Walker>(b)
+}
+#[bench]
+fn walker_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<Walker>(b)
+}
+#[bench]
+fn walker_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<Walker>(b)
+}
+#[bench]
+fn walker_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<Walker>(b)
+}
+#[bench]
+fn walker_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<Walker>(b)
+}
+#[bench]
+fn walker_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<Walker>(b)
+}
 // Bytecode
 #[bench]
 fn bytecode_compile(b: &mut Bencher) {
@@ -109,77 +467,1321 @@ fn bytecode_compile(b: &mut Bencher) {
 fn bytecode_execute(b: &mut Bencher) {
     bench_execute::<Bytecode>(b)
 }
-// Closures
 #[bench]
-fn closures_compile(b: &mut Bencher) {
-    bench_compile::<Closures>(b)
+fn bytecode_execute_small(b: &mut Bencher) {
+    bench_execute_small::<Bytecode>(b)
 }
 #[bench]
-fn closures_execute(b: &mut Bencher) {
-    bench_execute::<Closures>(b)
+fn bytecode_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<Bytecode>(b)
 }
-// Stack closures
 #[bench]
-fn stack_closures_compile(b: &mut Bencher) {
-    bench_compile::<StackClosures>(b)
+fn bytecode_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<Bytecode>(b)
 }
 #[bench]
-fn stack_closures_execute(b: &mut Bencher) {
-    bench_execute::<StackClosures>(b)
+fn bytecode_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<Bytecode>(b)
 }
-// Tape closures
 #[bench]
-fn tape_closures_compile(b: &mut Bencher) {
-    bench_compile::<TapeClosures>(b)
+fn bytecode_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<Bytecode>(b)
 }
 #[bench]
-fn tape_closures_execute(b: &mut Bencher) {
-    bench_execute::<TapeClosures>(b)
+fn bytecode_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<Bytecode>(b)
 }
-// Register closures
 #[bench]
-fn register_closures_compile(b: &mut Bencher) {
-    bench_compile::<RegisterClosures>(b)
+fn bytecode_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<Bytecode>(b)
 }
 #[bench]
-fn register_closures_execute(b: &mut Bencher) {
-    bench_execute::<RegisterClosures>(b)
+fn bytecode_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<Bytecode>(b)
 }
-// Bytecode closures
 #[bench]
-fn bytecode_closures_compile(b: &mut Bencher) {
-    bench_compile::<BytecodeClosures>(b)
+fn bytecode_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<Bytecode>(b)
 }
+// Bytecode with top-of-stack caching
 #[bench]
-fn bytecode_closures_execute(b: &mut Bencher) {
-    bench_execute::<BytecodeClosures>(b)
+fn stack_cached_bytecode_compile(b: &mut Bencher) {
+    bench_compile::<StackCachedBytecode>(b)
 }
-// Tape closures
 #[bench]
-fn tape_continuations_compile(b: &mut Bencher) {
-    bench_compile::<TapeContinuations>(b)
+fn stack_cached_bytecode_execute(b: &mut Bencher) {
+    bench_execute::<StackCachedBytecode>(b)
 }
 #[bench]
-fn tape_continuations_execute(b: &mut Bencher) {
-    bench_execute::<TapeContinuations>(b)
+fn stack_cached_bytecode_execute_small(b: &mut Bencher) {
+    bench_execute_small::<StackCachedBytecode>(b)
 }
-// Closure continuations
 #[bench]
-fn closure_continuations_compile(b: &mut Bencher) {
-    bench_compile::<ClosureContinuations>(b)
+fn stack_cached_bytecode_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<StackCachedBytecode>(b)
 }
 #[bench]
-fn closure_continuations_execute(b: &mut Bencher) {
-    bench_execute::<ClosureContinuations>(b)
+fn stack_cached_bytecode_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<StackCachedBytecode>(b)
 }
-// Closure stack continuations
 #[bench]
-fn closure_stack_continuations_compile(b: &mut Bencher) {
-    bench_compile::<ClosureStackContinuations>(b)
+fn stack_cached_bytecode_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<StackCachedBytecode>(b)
 }
 #[bench]
-fn closure_stack_continuations_execute(b: &mut Bencher) {
-    bench_execute::<ClosureStackContinuations>(b)
+fn stack_cached_bytecode_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<StackCachedBytecode>(b)
+}
+#[bench]
+fn stack_cached_bytecode_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<StackCachedBytecode>(b)
+}
+#[bench]
+fn stack_cached_bytecode_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<StackCachedBytecode>(b)
+}
+#[bench]
+fn stack_cached_bytecode_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<StackCachedBytecode>(b)
+}
+#[bench]
+fn stack_cached_bytecode_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<StackCachedBytecode>(b)
+}
+// Register bytecode
+#[bench]
+fn register_bytecode_compile(b: &mut Bencher) {
+    bench_compile::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute(b: &mut Bencher) {
+    bench_execute::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_small(b: &mut Bencher) {
+    bench_execute_small::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<RegisterBytecode>(b)
+}
+#[bench]
+fn register_bytecode_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<RegisterBytecode>(b)
+}
+// Threaded bytecode
+#[bench]
+fn threaded_bytecode_compile(b: &mut Bencher) {
+    bench_compile::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute(b: &mut Bencher) {
+    bench_execute::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_small(b: &mut Bencher) {
+    bench_execute_small::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<ThreadedBytecode>(b)
+}
+#[bench]
+fn threaded_bytecode_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<ThreadedBytecode>(b)
+}
+// Replicated-dispatch bytecode
+#[bench]
+fn replicated_bytecode_compile(b: &mut Bencher) {
+    bench_compile::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute(b: &mut Bencher) {
+    bench_execute::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_small(b: &mut Bencher) {
+    bench_execute_small::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<ReplicatedBytecode>(b)
+}
+#[bench]
+fn replicated_bytecode_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<ReplicatedBytecode>(b)
+}
+// Profile-driven superinstructions
+#[bench]
+fn super_bytecode_compile(b: &mut Bencher) {
+    bench_compile::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute(b: &mut Bencher) {
+    bench_execute::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_small(b: &mut Bencher) {
+    bench_execute_small::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<SuperBytecode>(b)
+}
+#[bench]
+fn super_bytecode_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<SuperBytecode>(b)
+}
+// Bytecode with a tracing JIT for hot loops
+#[bench]
+fn tracing_bytecode_compile(b: &mut Bencher) {
+    bench_compile::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute(b: &mut Bencher) {
+    bench_execute::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_small(b: &mut Bencher) {
+    bench_execute_small::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<TracingBytecode>(b)
+}
+#[bench]
+fn tracing_bytecode_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<TracingBytecode>(b)
+}
+
+// Bytecode, compiled via the SSA IR
+#[bench]
+fn ssa_bytecode_compile(b: &mut Bencher) {
+    bench_compile::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute(b: &mut Bencher) {
+    bench_execute::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_small(b: &mut Bencher) {
+    bench_execute_small::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<Ssa<Bytecode>>(b)
+}
+#[bench]
+fn ssa_bytecode_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<Ssa<Bytecode>>(b)
+}
+
+// Closures, compiled via the SSA IR
+#[bench]
+fn ssa_closures_compile(b: &mut Bencher) {
+    bench_compile::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute(b: &mut Bencher) {
+    bench_execute::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_small(b: &mut Bencher) {
+    bench_execute_small::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<Ssa<Closures>>(b)
+}
+#[bench]
+fn ssa_closures_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<Ssa<Closures>>(b)
+}
+
+// Closures
+#[bench]
+fn closures_compile(b: &mut Bencher) {
+    bench_compile::<Closures>(b)
+}
+#[bench]
+fn closures_compile_large(b: &mut Bencher) {
+    bench_compile_large::<Closures>(b)
+}
+#[bench]
+fn closures_execute(b: &mut Bencher) {
+    bench_execute::<Closures>(b)
+}
+#[bench]
+fn closures_execute_small(b: &mut Bencher) {
+    bench_execute_small::<Closures>(b)
+}
+#[bench]
+fn closures_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<Closures>(b)
+}
+#[bench]
+fn closures_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<Closures>(b)
+}
+#[bench]
+fn closures_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<Closures>(b)
+}
+#[bench]
+fn closures_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<Closures>(b)
+}
+#[bench]
+fn closures_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<Closures>(b)
+}
+#[bench]
+fn closures_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<Closures>(b)
+}
+#[bench]
+fn closures_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<Closures>(b)
+}
+#[bench]
+fn closures_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<Closures>(b)
+}
+#[bench]
+fn closures_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<Closures>(b)
+}
+// Stack closures
+#[bench]
+fn stack_closures_compile(b: &mut Bencher) {
+    bench_compile::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute(b: &mut Bencher) {
+    bench_execute::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_small(b: &mut Bencher) {
+    bench_execute_small::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<StackClosures>(b)
+}
+#[bench]
+fn stack_closures_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<StackClosures>(b)
+}
+// Tape closures
+#[bench]
+fn tape_closures_compile(b: &mut Bencher) {
+    bench_compile::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute(b: &mut Bencher) {
+    bench_execute::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_small(b: &mut Bencher) {
+    bench_execute_small::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<TapeClosures>(b)
+}
+#[bench]
+fn tape_closures_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<TapeClosures>(b)
+}
+// Register closures
+#[bench]
+fn register_closures_compile(b: &mut Bencher) {
+    bench_compile::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute(b: &mut Bencher) {
+    bench_execute::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_small(b: &mut Bencher) {
+    bench_execute_small::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<RegisterClosures<2>>(b)
+}
+// How the number of registers affects programs with many locals
+#[bench]
+fn register_closures_0_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<0>>(b)
+}
+#[bench]
+fn register_closures_1_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<1>>(b)
+}
+#[bench]
+fn register_closures_2_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<2>>(b)
+}
+#[bench]
+fn register_closures_3_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<3>>(b)
+}
+#[bench]
+fn register_closures_4_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<4>>(b)
+}
+#[bench]
+fn register_closures_5_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<5>>(b)
+}
+#[bench]
+fn register_closures_6_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<6>>(b)
+}
+#[bench]
+fn register_closures_7_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<7>>(b)
+}
+#[bench]
+fn register_closures_8_execute_many_locals(b: &mut Bencher) {
+    bench_execute_many_locals::<RegisterClosures<8>>(b)
+}
+// Bytecode closures
+#[bench]
+fn bytecode_closures_compile(b: &mut Bencher) {
+    bench_compile::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute(b: &mut Bencher) {
+    bench_execute::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_small(b: &mut Bencher) {
+    bench_execute_small::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<BytecodeClosures>(b)
+}
+#[bench]
+fn bytecode_closures_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<BytecodeClosures>(b)
+}
+// Tape closures
+#[bench]
+fn tape_continuations_compile(b: &mut Bencher) {
+    bench_compile::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute(b: &mut Bencher) {
+    bench_execute::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_small(b: &mut Bencher) {
+    bench_execute_small::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<TapeContinuations>(b)
+}
+#[bench]
+fn tape_continuations_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<TapeContinuations>(b)
+}
+// Tape continuations with guaranteed tail calls
+#[bench]
+fn tape_tail_calls_compile(b: &mut Bencher) {
+    bench_compile::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute(b: &mut Bencher) {
+    bench_execute::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_small(b: &mut Bencher) {
+    bench_execute_small::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<TapeTailCalls>(b)
+}
+#[bench]
+fn tape_tail_calls_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<TapeTailCalls>(b)
+}
+// Closure continuations
+#[bench]
+fn closure_continuations_compile(b: &mut Bencher) {
+    bench_compile::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_compile_large(b: &mut Bencher) {
+    bench_compile_large::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute(b: &mut Bencher) {
+    bench_execute::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_small(b: &mut Bencher) {
+    bench_execute_small::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<ClosureContinuations>(b)
+}
+#[bench]
+fn closure_continuations_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<ClosureContinuations>(b)
+}
+// Closure stack continuations
+#[bench]
+fn closure_stack_continuations_compile(b: &mut Bencher) {
+    bench_compile::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_compile_large(b: &mut Bencher) {
+    bench_compile_large::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute(b: &mut Bencher) {
+    bench_execute::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_guarded_execute(b: &mut Bencher) {
+    bench_execute::<ClosureStackContinuations<Guarded>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_small(b: &mut Bencher) {
+    bench_execute_small::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<ClosureStackContinuations<Presized>>(b)
+}
+#[bench]
+fn closure_stack_continuations_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<ClosureStackContinuations<Presized>>(b)
+}
+// Closure continuations with guaranteed tail calls
+#[bench]
+fn closure_tail_calls_compile(b: &mut Bencher) {
+    bench_compile::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_compile_large(b: &mut Bencher) {
+    bench_compile_large::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute(b: &mut Bencher) {
+    bench_execute::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_small(b: &mut Bencher) {
+    bench_execute_small::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<ClosureTailCalls>(b)
+}
+#[bench]
+fn closure_tail_calls_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<ClosureTailCalls>(b)
+}
+// SIMD closures (4 lanes)
+#[bench]
+fn simd_closures_4_compile(b: &mut Bencher) {
+    bench_compile::<SimdClosures<4>>(b)
+}
+#[bench]
+fn simd_closures_4_execute(b: &mut Bencher) {
+    bench_execute::<SimdClosures<4>>(b)
+}
+#[bench]
+fn simd_closures_4_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<SimdClosures<4>>(b)
+}
+// SIMD closures (8 lanes)
+#[bench]
+fn simd_closures_8_compile(b: &mut Bencher) {
+    bench_compile::<SimdClosures<8>>(b)
+}
+#[bench]
+fn simd_closures_8_execute(b: &mut Bencher) {
+    bench_execute::<SimdClosures<8>>(b)
+}
+#[bench]
+fn simd_closures_8_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<SimdClosures<8>>(b)
+}
+// SIMD closures (16 lanes)
+#[bench]
+fn simd_closures_16_compile(b: &mut Bencher) {
+    bench_compile::<SimdClosures<16>>(b)
+}
+#[bench]
+fn simd_closures_16_execute(b: &mut Bencher) {
+    bench_execute::<SimdClosures<16>>(b)
+}
+#[bench]
+fn simd_closures_16_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<SimdClosures<16>>(b)
+}
+
+// Native x86-64 JIT
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_compile(b: &mut Bencher) {
+    bench_compile::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_compile_large(b: &mut Bencher) {
+    bench_compile_large::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute(b: &mut Bencher) {
+    bench_execute::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_small(b: &mut Bencher) {
+    bench_execute_small::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<Jit>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn jit_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<Jit>(b)
+}
+
+// Copy-and-patch
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_compile(b: &mut Bencher) {
+    bench_compile::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_compile_large(b: &mut Bencher) {
+    bench_compile_large::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute(b: &mut Bencher) {
+    bench_execute::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_small(b: &mut Bencher) {
+    bench_execute_small::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<CopyAndPatch>(b)
+}
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[bench]
+fn copy_and_patch_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<CopyAndPatch>(b)
+}
+
+// Rust, compiled ahead of time by rustc. Compiles are cached on disk, so after the first run `compile` only measures
+// generating and hashing the source and loading the library.
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_compile(b: &mut Bencher) {
+    bench_compile::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute(b: &mut Bencher) {
+    bench_execute::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_small(b: &mut Bencher) {
+    bench_execute_small::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<RustcAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn rustc_aot_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<RustcAot>(b)
+}
+
+// C, compiled ahead of time by the system C compiler, and cached like `RustcAot`
+#[cfg(unix)]
+#[bench]
+fn c_aot_compile(b: &mut Bencher) {
+    bench_compile::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute(b: &mut Bencher) {
+    bench_execute::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_small(b: &mut Bencher) {
+    bench_execute_small::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<CAot>(b)
+}
+#[cfg(unix)]
+#[bench]
+fn c_aot_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<CAot>(b)
+}
+
+// Tiered: the AST walker, promoted to closure continuations after 64 executions
+#[bench]
+fn tiered_compile(b: &mut Bencher) {
+    bench_compile::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute(b: &mut Bencher) {
+    bench_execute::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_small(b: &mut Bencher) {
+    bench_execute_small::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_small_reuse(b: &mut Bencher) {
+    bench_execute_small_reuse::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_parallel(b: &mut Bencher) {
+    bench_execute_parallel::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_batch(b: &mut Bencher) {
+    bench_execute_batch::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_per_call(b: &mut Bencher) {
+    bench_execute_per_call::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_specialized(b: &mut Bencher) {
+    bench_execute_specialized::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_redundant(b: &mut Bencher) {
+    bench_execute_redundant::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_optimized(b: &mut Bencher) {
+    bench_execute_optimized::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+#[bench]
+fn tiered_execute_closed_form(b: &mut Bencher) {
+    bench_execute_closed_form::<Tiered<Walker, ClosureContinuations, 64>>(b)
+}
+
+// Crossover between tiers, by the number of times each program is executed
+#[bench]
+fn walker_calls_1(b: &mut Bencher) {
+    bench_calls::<Walker, 1>(b)
+}
+#[bench]
+fn walker_calls_10(b: &mut Bencher) {
+    bench_calls::<Walker, 10>(b)
+}
+#[bench]
+fn walker_calls_100(b: &mut Bencher) {
+    bench_calls::<Walker, 100>(b)
+}
+#[bench]
+fn walker_calls_1000(b: &mut Bencher) {
+    bench_calls::<Walker, 1000>(b)
+}
+#[bench]
+fn walker_calls_10000(b: &mut Bencher) {
+    bench_calls::<Walker, 10000>(b)
+}
+#[bench]
+fn bytecode_calls_1(b: &mut Bencher) {
+    bench_calls::<Bytecode, 1>(b)
+}
+#[bench]
+fn bytecode_calls_10(b: &mut Bencher) {
+    bench_calls::<Bytecode, 10>(b)
+}
+#[bench]
+fn bytecode_calls_100(b: &mut Bencher) {
+    bench_calls::<Bytecode, 100>(b)
+}
+#[bench]
+fn bytecode_calls_1000(b: &mut Bencher) {
+    bench_calls::<Bytecode, 1000>(b)
+}
+#[bench]
+fn bytecode_calls_10000(b: &mut Bencher) {
+    bench_calls::<Bytecode, 10000>(b)
+}
+#[bench]
+fn closure_continuations_calls_1(b: &mut Bencher) {
+    bench_calls::<ClosureContinuations, 1>(b)
+}
+#[bench]
+fn closure_continuations_calls_10(b: &mut Bencher) {
+    bench_calls::<ClosureContinuations, 10>(b)
+}
+#[bench]
+fn closure_continuations_calls_100(b: &mut Bencher) {
+    bench_calls::<ClosureContinuations, 100>(b)
+}
+#[bench]
+fn closure_continuations_calls_1000(b: &mut Bencher) {
+    bench_calls::<ClosureContinuations, 1000>(b)
+}
+#[bench]
+fn closure_continuations_calls_10000(b: &mut Bencher) {
+    bench_calls::<ClosureContinuations, 10000>(b)
+}
+#[bench]
+fn tiered_calls_1(b: &mut Bencher) {
+    bench_calls::<Tiered<Walker, ClosureContinuations, 64>, 1>(b)
+}
+#[bench]
+fn tiered_calls_10(b: &mut Bencher) {
+    bench_calls::<Tiered<Walker, ClosureContinuations, 64>, 10>(b)
+}
+#[bench]
+fn tiered_calls_100(b: &mut Bencher) {
+    bench_calls::<Tiered<Walker, ClosureContinuations, 64>, 100>(b)
+}
+#[bench]
+fn tiered_calls_1000(b: &mut Bencher) {
+    bench_calls::<Tiered<Walker, ClosureContinuations, 64>, 1000>(b)
+}
+#[bench]
+fn tiered_calls_10000(b: &mut Bencher) {
+    bench_calls::<Tiered<Walker, ClosureContinuations, 64>, 10000>(b)
 }
 
 // Pure Rust controls
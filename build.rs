@@ -0,0 +1,209 @@
+// Builds the stencils used by `CopyAndPatch`: `stencils/stencils.rs` is compiled to an object file, and the machine code
+// and relocations of each stencil are pulled out of it into `$OUT_DIR/stencils.rs`. Also tells `RustcAot` which rustc to
+// compile programs with.
+use std::{env, fmt::Write, fs, path::Path, process::Command};
+
+const STENCILS: &[&str] = &[
+    "litr",
+    "arg",
+    "get",
+    "add",
+    "push_local",
+    "pop_local",
+    "set_local",
+    "pop",
+    "jmp_zn",
+    "jmp",
+    "ret",
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=stencils/stencils.rs");
+    println!(
+        "cargo:rustc-env=VM_PERF_RUSTC={}",
+        env::var("RUSTC").unwrap()
+    );
+
+    // Must match the `cfg` on `copy_and_patch`
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    if arch != "x86_64" || os != "linux" {
+        return;
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let obj_path = Path::new(&out_dir).join("stencils.o");
+    let status = Command::new(env::var("RUSTC").unwrap())
+        .args(["--crate-type=lib", "--edition=2021", "--emit=obj"])
+        .args(["-C", "opt-level=3", "-C", "panic=abort"])
+        // Every reference to a hole becomes a 64-bit absolute relocation, which is trivial to patch
+        .args(["-C", "code-model=large", "-C", "relocation-model=static"])
+        .arg("--target")
+        .arg(env::var("TARGET").unwrap())
+        .arg("-o")
+        .arg(&obj_path)
+        .arg("stencils/stencils.rs")
+        .status()
+        .expect("failed to run rustc on stencils");
+    assert!(status.success(), "failed to compile stencils");
+
+    let obj = Elf(fs::read(&obj_path).unwrap());
+    let mut out = String::new();
+    for name in STENCILS {
+        let stencil = obj.stencil(&format!("stencil_{name}"));
+        writeln!(
+            out,
+            "pub(crate) const {}: Stencil = {stencil};",
+            name.to_uppercase()
+        )
+        .unwrap();
+    }
+    fs::write(Path::new(&out_dir).join("stencils.rs"), out).unwrap();
+}
+
+// Just enough of a 64-bit little-endian ELF relocatable object reader to find functions and their relocations
+struct Elf(Vec<u8>);
+
+struct Section {
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+    info: usize,
+}
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_RELA: u32 = 4;
+const R_X86_64_64: u32 = 1;
+
+impl Elf {
+    fn u16(&self, at: usize) -> u16 {
+        u16::from_le_bytes(self.0[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.0[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64(&self, at: usize) -> u64 {
+        u64::from_le_bytes(self.0[at..at + 8].try_into().unwrap())
+    }
+
+    fn str(&self, at: usize) -> &str {
+        let len = self.0[at..].iter().position(|b| *b == 0).unwrap();
+        std::str::from_utf8(&self.0[at..at + len]).unwrap()
+    }
+
+    fn sections(&self) -> Vec<Section> {
+        let shoff = self.u64(0x28) as usize;
+        let shentsize = self.u16(0x3A) as usize;
+        let shnum = self.u16(0x3C) as usize;
+        (0..shnum)
+            .map(|i| {
+                let at = shoff + i * shentsize;
+                Section {
+                    kind: self.u32(at + 4),
+                    offset: self.u64(at + 24) as usize,
+                    size: self.u64(at + 32) as usize,
+                    link: self.u32(at + 40) as usize,
+                    info: self.u32(at + 44) as usize,
+                }
+            })
+            .collect()
+    }
+
+    // (name, section, value, size) of the symbol at `idx` in `symtab`
+    fn symbol(
+        &self,
+        sections: &[Section],
+        symtab: &Section,
+        idx: usize,
+    ) -> (&str, usize, usize, usize) {
+        let at = symtab.offset + idx * 24;
+        let name = self.str(sections[symtab.link].offset + self.u32(at) as usize);
+        let shndx = self.u16(at + 6) as usize;
+        (
+            name,
+            shndx,
+            self.u64(at + 8) as usize,
+            self.u64(at + 16) as usize,
+        )
+    }
+
+    // Extract a stencil as the source of a `Stencil` constant
+    fn stencil(&self, func: &str) -> String {
+        let sections = self.sections();
+        let symtab = sections.iter().find(|s| s.kind == SHT_SYMTAB).unwrap();
+        let (shndx, start, size) = (0..symtab.size / 24)
+            .map(|idx| self.symbol(&sections, symtab, idx))
+            .find(|(name, ..)| *name == func)
+            .map(|(_, shndx, start, size)| (shndx, start, size))
+            .unwrap_or_else(|| panic!("no stencil `{func}`"));
+        let text = &sections[shndx];
+        let mut code = self.0[text.offset + start..text.offset + start + size].to_vec();
+
+        let mut holes = Vec::new();
+        for rela in sections
+            .iter()
+            .filter(|s| s.kind == SHT_RELA && s.info == shndx)
+        {
+            for at in (rela.offset..rela.offset + rela.size).step_by(24) {
+                let offset = self.u64(at) as usize;
+                let info = self.u64(at + 8);
+                let addend = self.u64(at + 16) as i64;
+                if offset < start || offset >= start + size {
+                    continue;
+                }
+                let (sym, ..) = self.symbol(&sections, &sections[rela.link], (info >> 32) as usize);
+                assert_eq!(
+                    info as u32, R_X86_64_64,
+                    "`{func}` has an unsupported relocation against `{sym}`"
+                );
+                let kind = match sym {
+                    "HOLE_OPERAND" => "Operand",
+                    "CONTINUE" => "Continue",
+                    "JUMP" => "Jump",
+                    _ => panic!("`{func}` refers to `{sym}`, which isn't a hole"),
+                };
+                holes.push((offset - start, kind, addend));
+            }
+        }
+
+        // A stencil that ends by jumping straight to the next one (`movabs reg, CONTINUE; jmp reg`) can just fall through
+        // into it instead, since the next op's stencil is always placed right after
+        let tail_jump = |offset: usize| {
+            // The register encoding of the `movabs` has to match that of the `jmp`
+            let reg = match code[offset - 2..offset] {
+                [rex @ (0x48 | 0x49), op @ 0xB8..=0xBF] => (rex == 0x49, op - 0xB8),
+                _ => return false,
+            };
+            match code[offset + 8..] {
+                [0xFF, modrm @ 0xE0..=0xE7] => reg == (false, modrm - 0xE0),
+                [0x41, 0xFF, modrm @ 0xE0..=0xE7] => reg == (true, modrm - 0xE0),
+                _ => false,
+            }
+        };
+        if let Some(last) = holes
+            .iter()
+            .position(|(offset, kind, _)| *kind == "Continue" && tail_jump(*offset))
+        {
+            code.truncate(holes[last].0 - 2);
+            holes.remove(last);
+        }
+
+        let code = code
+            .iter()
+            .map(|b| format!("{b:#04x}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let holes = holes
+            .iter()
+            .map(|(offset, kind, addend)| {
+                format!("Hole {{ offset: {offset}, kind: HoleKind::{kind}, addend: {addend} }}")
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("Stencil {{ code: &[{code}], holes: &[{holes}] }}")
+    }
+}
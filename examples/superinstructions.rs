@@ -0,0 +1,93 @@
+// Profiles `Bytecode` over a set of workloads and prints the `superinstructions!` table used by `SuperBytecode`
+use vm_perf::{builder::*, super_bytecode::Profile, Bytecode, Expr, Vm};
+
+// How many superinstructions to generate
+const TOP_K: usize = 4;
+
+fn workloads() -> Vec<(Box<Expr>, Vec<i64>)> {
+    vec![
+        // Sum: total += args[1], args[0] times
+        (
+            let_(
+                litr(0),
+                then(
+                    let_(
+                        arg(0),
+                        while_(
+                            get(0),
+                            then(set(1, add(get(1), arg(1))), set(0, add(get(0), litr(-1)))),
+                        ),
+                    ),
+                    get(0),
+                ),
+            ),
+            vec![10000, 13],
+        ),
+        // Nested loops: total += i, for each j in 0..args[0], for each i in 0..args[1]
+        (
+            let_(
+                litr(0),
+                then(
+                    let_(
+                        arg(0),
+                        while_(
+                            get(0),
+                            then(
+                                let_(
+                                    arg(1),
+                                    while_(
+                                        get(0),
+                                        then(
+                                            set(2, add(get(2), get(0))),
+                                            set(0, add(get(0), litr(-1))),
+                                        ),
+                                    ),
+                                ),
+                                set(0, add(get(0), litr(-1))),
+                            ),
+                        ),
+                    ),
+                    get(0),
+                ),
+            ),
+            vec![100, 100],
+        ),
+        // Fibonacci: args[0] steps
+        (
+            let_(
+                litr(0),
+                let_(
+                    litr(1),
+                    then(
+                        let_(
+                            arg(0),
+                            while_(
+                                get(0),
+                                let_(
+                                    add(get(1), get(2)),
+                                    then(
+                                        then(set(3, get(2)), set(2, get(0))),
+                                        set(1, add(get(1), litr(-1))),
+                                    ),
+                                ),
+                            ),
+                        ),
+                        get(1),
+                    ),
+                ),
+            ),
+            vec![50, 0],
+        ),
+    ]
+}
+
+fn main() {
+    let mut profile = Profile::default();
+    for (expr, args) in workloads() {
+        let prog = Bytecode::compile(&expr);
+        unsafe {
+            profile.record(&prog, &args);
+        }
+    }
+    print!("{}", profile.table(TOP_K));
+}
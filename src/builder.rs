@@ -0,0 +1,51 @@
+use super::*;
+
+// Shorthand for writing `Expr`s by hand, for tests, examples and passes that build programs. Each function builds one
+// node, boxed so that they nest.
+
+pub fn litr(x: i64) -> Box<Expr> {
+    Box::new(Expr::Litr(x))
+}
+
+pub fn arg(idx: usize) -> Box<Expr> {
+    Box::new(Expr::Arg(idx))
+}
+
+pub fn get(local: LocalOffset) -> Box<Expr> {
+    Box::new(Expr::Get(local))
+}
+
+pub fn add(x: Box<Expr>, y: Box<Expr>) -> Box<Expr> {
+    Box::new(Expr::Add(x, y))
+}
+
+pub fn let_(rhs: Box<Expr>, then: Box<Expr>) -> Box<Expr> {
+    Box::new(Expr::Let(rhs, then))
+}
+
+pub fn set(local: LocalOffset, rhs: Box<Expr>) -> Box<Expr> {
+    Box::new(Expr::Set(local, rhs))
+}
+
+pub fn while_(pred: Box<Expr>, body: Box<Expr>) -> Box<Expr> {
+    Box::new(Expr::While(pred, body))
+}
+
+pub fn then(a: Box<Expr>, b: Box<Expr>) -> Box<Expr> {
+    Box::new(Expr::Then(a, b))
+}
+
+/// `let total = 0; let n = args[0]; while n > 0 { <body>; n = n + -1 }; total`, where `body` sees `n` as local 0 and
+/// `total` as local 1.
+pub fn counted_loop(body: Box<Expr>) -> Expr {
+    *let_(
+        litr(0),
+        then(
+            let_(
+                arg(0),
+                while_(get(0), then(body, set(0, add(get(0), litr(-1))))),
+            ),
+            get(0),
+        ),
+    )
+}
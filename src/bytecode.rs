@@ -2,7 +2,7 @@ use super::*;
 
 pub struct Bytecode;
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub enum Op {
     Litr(i64),
     Arg(usize),
@@ -18,18 +18,10 @@ pub enum Op {
 }
 
 impl Vm for Bytecode {
-    type Program<'a> = Vec<Op>;
+    type Program<'a> = Frame<Vec<Op>>;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
-        fn returns(expr: &Expr) -> bool {
-            match expr {
-                Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) | Expr::Add(_, _) => true,
-                Expr::Let(_, expr) => returns(expr),
-                Expr::Set(_, _) | Expr::While(_, _) => false,
-                Expr::Then(_, b) => returns(b),
-            }
-        }
-
         fn compile_inner(ops: &mut Vec<Op>, expr: &Expr) {
             match expr {
                 Expr::Litr(x) => ops.push(Op::Litr(*x)),
@@ -56,7 +48,7 @@ impl Vm for Bytecode {
                     let branch_fixup = ops.len();
                     ops.push(Op::JmpZN(0)); // Will be fixed up
                     compile_inner(ops, body);
-                    if returns(body) {
+                    if body.returns() {
                         ops.push(Op::Pop);
                     }
                     ops.push(Op::Jmp(start));
@@ -64,7 +56,7 @@ impl Vm for Bytecode {
                 }
                 Expr::Then(a, b) => {
                     compile_inner(ops, a);
-                    if returns(a) {
+                    if a.returns() {
                         ops.push(Op::Pop);
                     }
                     compile_inner(ops, b);
@@ -78,47 +70,78 @@ impl Vm for Bytecode {
 
         ops.push(Op::Ret);
 
-        ops
+        Frame {
+            code: ops,
+            max_locals: expr.max_locals(),
+            max_stack: expr.max_stack(),
+        }
     }
 
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
+    }
+
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
         let mut ip = 0;
-        let mut stack = Vec::new();
-        let mut locals = Vec::new();
+        stack.clear();
+        locals.clear();
+        stack.reserve(prog.max_stack);
+        locals.reserve(prog.max_locals);
         loop {
-            let op = prog.get_unchecked(ip);
+            let op = prog.code.get_unchecked(ip);
             ip += 1;
-            match op {
-                Op::Litr(x) => stack.push(*x),
-                Op::Arg(idx) => stack.push(*args.get_unchecked(*idx)),
-                Op::Get(local) => stack.push(*locals.get_unchecked(locals.len() - local - 1)),
-                Op::Add => {
-                    let x = stack.pop().unwrap_unchecked();
-                    let y = stack.pop().unwrap_unchecked();
-                    stack.push(x + y);
-                }
-                Op::PushLocal => locals.push(stack.pop().unwrap_unchecked()),
-                Op::PopLocal => unsafe {
-                    locals.pop().unwrap_unchecked();
-                },
-                Op::SetLocal(local) => {
-                    let rhs = stack.pop().unwrap_unchecked();
-                    let local_offs = locals.len() - local - 1;
-                    unsafe {
-                        *locals.get_unchecked_mut(local_offs) = rhs;
-                    }
+            if let Some(res) = Self::step(op, &mut ip, args, stack, locals) {
+                break res;
+            }
+        }
+    }
+}
+
+impl Bytecode {
+    // Perform a single op, producing the result of the program if it has finished
+    #[inline(always)]
+    pub(crate) unsafe fn step(
+        op: &Op,
+        ip: &mut usize,
+        args: &[i64],
+        stack: &mut Vec<i64>,
+        locals: &mut Vec<i64>,
+    ) -> Option<i64> {
+        match op {
+            Op::Litr(x) => stack.push(*x),
+            Op::Arg(idx) => stack.push(*args.get_unchecked(*idx)),
+            Op::Get(local) => stack.push(*locals.get_unchecked(locals.len() - local - 1)),
+            Op::Add => {
+                let x = stack.pop().unwrap_unchecked();
+                let y = stack.pop().unwrap_unchecked();
+                stack.push(x + y);
+            }
+            Op::PushLocal => locals.push(stack.pop().unwrap_unchecked()),
+            Op::PopLocal => unsafe {
+                locals.pop().unwrap_unchecked();
+            },
+            Op::SetLocal(local) => {
+                let rhs = stack.pop().unwrap_unchecked();
+                let local_offs = locals.len() - local - 1;
+                unsafe {
+                    *locals.get_unchecked_mut(local_offs) = rhs;
                 }
-                Op::Pop => unsafe {
-                    stack.pop().unwrap_unchecked();
-                },
-                Op::JmpZN(goto) => {
-                    if stack.pop().unwrap_unchecked() <= 0 {
-                        ip = *goto;
-                    }
+            }
+            Op::Pop => unsafe {
+                stack.pop().unwrap_unchecked();
+            },
+            Op::JmpZN(goto) => {
+                if stack.pop().unwrap_unchecked() <= 0 {
+                    *ip = *goto;
                 }
-                Op::Jmp(goto) => ip = *goto,
-                Op::Ret => break stack.pop().unwrap_unchecked(),
             }
+            Op::Jmp(goto) => *ip = *goto,
+            Op::Ret => return Some(stack.pop().unwrap_unchecked()),
         }
+        None
     }
 }
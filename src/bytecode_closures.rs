@@ -17,43 +17,45 @@ pub struct BytecodeClosures;
 //     Ret,
 // }
 
-type OpFn<'a> = Box<
+type OpFn = Box<
     dyn Fn(
             &mut usize,
             &[i64],        // args
             &mut Vec<i64>, // stack
             &mut Vec<i64>, // locals
         ) -> bool
-        + 'a,
+        + Send
+        + Sync,
 >;
 
 impl Vm for BytecodeClosures {
-    type Program<'a> = Vec<OpFn<'a>>;
+    type Program<'a> = Frame<Vec<OpFn>>;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
-        fn returns(expr: &Expr) -> bool {
+        unsafe fn compile_inner(ops: &mut Vec<OpFn>, expr: &Expr) {
             match expr {
-                Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) | Expr::Add(_, _) => true,
-                Expr::Let(_, expr) => returns(expr),
-                Expr::Set(_, _) | Expr::While(_, _) => false,
-                Expr::Then(_, b) => returns(b),
-            }
-        }
-
-        unsafe fn compile_inner<'a>(ops: &mut Vec<OpFn<'a>>, expr: &'a Expr) {
-            match expr {
-                Expr::Litr(x) => ops.push(Box::new(move |_, _, stack, _| {
-                    stack.push(*x);
-                    false
-                })),
-                Expr::Arg(idx) => ops.push(Box::new(move |_, args, stack, _| {
-                    stack.push(*args.get_unchecked(*idx));
-                    false
-                })),
-                Expr::Get(local) => ops.push(Box::new(move |_, _, stack, locals| {
-                    stack.push(*locals.get_unchecked(locals.len() - local - 1));
-                    false
-                })),
+                Expr::Litr(x) => {
+                    let x = *x;
+                    ops.push(Box::new(move |_, _, stack, _| {
+                        stack.push(x);
+                        false
+                    }))
+                }
+                Expr::Arg(idx) => {
+                    let idx = *idx;
+                    ops.push(Box::new(move |_, args, stack, _| {
+                        stack.push(*args.get_unchecked(idx));
+                        false
+                    }))
+                }
+                Expr::Get(local) => {
+                    let local = *local;
+                    ops.push(Box::new(move |_, _, stack, locals| {
+                        stack.push(*locals.get_unchecked(locals.len() - local - 1));
+                        false
+                    }))
+                }
                 Expr::Add(x, y) => {
                     compile_inner(ops, x);
                     compile_inner(ops, y);
@@ -77,6 +79,7 @@ impl Vm for BytecodeClosures {
                     }));
                 }
                 Expr::Set(local, rhs) => {
+                    let local = *local;
                     compile_inner(ops, rhs);
                     ops.push(Box::new(move |_, _, stack, locals| {
                         let rhs = stack.pop().unwrap_unchecked();
@@ -91,7 +94,7 @@ impl Vm for BytecodeClosures {
                     let branch_fixup = ops.len();
                     ops.push(Box::new(|_, _, _, _| false)); // Will be fixed up
                     compile_inner(ops, body);
-                    if returns(body) {
+                    if body.returns() {
                         ops.push(Box::new(move |_, _, stack, _| {
                             stack.pop().unwrap_unchecked();
                             false
@@ -111,7 +114,7 @@ impl Vm for BytecodeClosures {
                 }
                 Expr::Then(a, b) => {
                     compile_inner(ops, a);
-                    if returns(a) {
+                    if a.returns() {
                         ops.push(Box::new(move |_, _, stack, _| {
                             stack.pop().unwrap_unchecked();
                             false
@@ -130,17 +133,31 @@ impl Vm for BytecodeClosures {
 
         ops.push(Box::new(move |_, _, _, _| true));
 
-        ops
+        Frame {
+            code: ops,
+            max_locals: expr.max_locals(),
+            max_stack: expr.max_stack(),
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
     }
 
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
         let mut ip = 0;
-        let mut stack = Vec::new();
-        let mut locals = Vec::new();
+        stack.clear();
+        locals.clear();
+        stack.reserve(prog.max_stack);
+        locals.reserve(prog.max_locals);
         loop {
-            let f = prog.get_unchecked(ip);
+            let f = prog.code.get_unchecked(ip);
             ip += 1;
-            if f(&mut ip, args, &mut stack, &mut locals) {
+            if f(&mut ip, args, stack, locals) {
                 break stack.pop().unwrap_unchecked();
             }
         }
@@ -0,0 +1,125 @@
+use super::*;
+use crate::rustc_aot::{Compiler, Library, ENTRY};
+use std::fmt::Write;
+
+// `RustcAot`, but lowering to C99 and building with the system C compiler: a second native baseline, with a different
+// optimiser. Libraries share `RustcAot`'s cache, where the generated C is kept next to each one, so they get the same
+// checks before they're loaded.
+pub struct CAot;
+
+const CC: Compiler = Compiler {
+    program: "cc",
+    flags: &["-std=c99", "-O2", "-shared", "-fPIC"],
+    extension: "c",
+};
+
+// Lowers an expression to the body of a C function, as a flat list of statements (see `rustc_aot::Lowering`)
+struct Lowering {
+    out: String,
+    // Names of the locals in scope, innermost last. C doesn't allow shadowing within a block, so every `Let` gets a fresh
+    // variable.
+    locals: Vec<String>,
+    vars: usize,
+}
+
+impl Lowering {
+    // Emit the statements for an expression, and return a C operand for its value
+    fn lower(&mut self, expr: &Expr) -> String {
+        // A stand-in for expressions that don't return anything
+        const UNIT: &str = "0";
+
+        match expr {
+            // `INT64_MIN` can't be written as a negated literal, since the literal itself would be out of range
+            Expr::Litr(i64::MIN) => "INT64_MIN".to_string(),
+            Expr::Litr(x) => format!("INT64_C({x})"),
+            Expr::Arg(idx) => format!("args[{idx}]"),
+            // Read into a temporary, since the local may be set before the value is used
+            Expr::Get(local) => {
+                let value = self.local(*local).to_string();
+                self.var("t", value)
+            }
+            Expr::Add(x, y) => {
+                let x = self.lower(x);
+                let y = self.lower(y);
+                // Signed overflow is undefined in C, so add as unsigned and convert back, which wraps like Rust's
+                // release builds
+                self.var("t", format!("(int64_t)((uint64_t){x} + (uint64_t){y})"))
+            }
+            Expr::Let(rhs, then) => {
+                let rhs = self.lower(rhs);
+                let local = self.var("l", rhs);
+                self.locals.push(local);
+                let then = self.lower(then);
+                self.locals.pop();
+                then
+            }
+            Expr::Set(local, rhs) => {
+                let rhs = self.lower(rhs);
+                let local = self.local(*local).to_string();
+                writeln!(self.out, "{local} = {rhs};").unwrap();
+                UNIT.to_string()
+            }
+            Expr::While(pred, body) => {
+                self.out.push_str("for (;;) {\n");
+                let pred = self.lower(pred);
+                writeln!(self.out, "if ({pred} <= 0) break;").unwrap();
+                self.lower(body);
+                self.out.push_str("}\n");
+                UNIT.to_string()
+            }
+            Expr::Then(a, b) => {
+                self.lower(a);
+                self.lower(b)
+            }
+        }
+    }
+
+    fn local(&self, local: LocalOffset) -> &str {
+        &self.locals[self.locals.len() - local - 1]
+    }
+
+    fn var(&mut self, prefix: &str, value: String) -> String {
+        let var = format!("{prefix}{}", self.vars);
+        self.vars += 1;
+        writeln!(self.out, "int64_t {var} = {value};").unwrap();
+        var
+    }
+}
+
+impl CAot {
+    /// The C source that a program compiles to.
+    pub fn source(expr: &Expr) -> String {
+        let mut lowering = Lowering {
+            out: String::new(),
+            locals: Vec::new(),
+            vars: 0,
+        };
+        let result = lowering.lower(expr);
+        format!(
+            "#include <stdint.h>\n\
+             \n\
+             int64_t {}(const int64_t *args) {{\n{}return {result};\n}}\n",
+            ENTRY.to_str().unwrap(),
+            lowering.out,
+        )
+    }
+}
+
+impl Vm for CAot {
+    type Program<'a> = Frame<Library>;
+    type Context = ();
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        Frame {
+            code: CC.load(&Self::source(expr)),
+            max_locals: expr.max_locals(),
+            max_stack: 0, // Intermediate values are up to the C compiler
+        }
+    }
+
+    fn new_context() -> Self::Context {}
+
+    unsafe fn execute_in(_: &mut Self::Context, prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+        (prog.code.entry())(args.as_ptr())
+    }
+}
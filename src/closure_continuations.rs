@@ -1,27 +1,41 @@
 use super::*;
+use crate::rewrite::Rewrite;
 use core::marker::PhantomData;
 
 pub struct ClosureContinuations;
 
 impl Vm for ClosureContinuations {
-    type Program<'a> = Func<'a>;
+    type Program<'a> = Frame<Func<'static>>;
+    type Context = Vec<i64>; // locals
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
-        unsafe { Self::compile(expr, ()) }
+        Frame {
+            code: unsafe { Self::compile(expr, ()) },
+            max_locals: expr.max_locals(),
+            max_stack: 0, // Intermediate values live on the hardware stack
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        Vec::new()
     }
 
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
-        let mut v = vec![0; 64];
-        prog.invoke(args.as_ptr(), v.as_mut_ptr(), 0)
+    unsafe fn execute_in(
+        locals: &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        reserve_slots(locals, prog.max_locals);
+        prog.code.invoke(args.as_ptr(), locals.as_mut_ptr(), 0)
     }
 }
 
-trait MaybeCont<'a> {
+trait MaybeCont<'a>: Send + Sync {
     #[inline(always)]
-    fn cont(&self, args: *const i64, locals: *mut i64, result: i64) -> i64 {
+    fn cont(&self, _args: *const i64, _locals: *mut i64, result: i64) -> i64 {
         result
     }
-    fn map(self, f: impl FnOnce(Self) -> Func<'a>) -> Self
+    fn map(self, _f: impl FnOnce(Self) -> Func<'a>) -> Self
     where
         Self: Sized,
     {
@@ -49,17 +63,29 @@ impl<'a> MaybeCont<'a> for Func<'a> {
 pub struct Func<'a> {
     f: unsafe fn(*const (), *const i64, *mut i64, i64) -> i64,
     data: *const (),
+    // Frees `data`, which is type-erased
+    drop: unsafe fn(*const ()),
     phantom: PhantomData<&'a ()>,
 }
 
+// SAFETY: `make_func` only accepts closures that are `Send + Sync`, and `data` is never mutated after construction.
+unsafe impl<'a> Send for Func<'a> {}
+unsafe impl<'a> Sync for Func<'a> {}
+
+impl<'a> Drop for Func<'a> {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
 impl<'a> Func<'a> {
     #[inline(always)]
-    pub fn invoke(&self, args: *const i64, locals: *mut i64, ret: i64) -> i64 {
+    pub(crate) fn invoke(&self, args: *const i64, locals: *mut i64, ret: i64) -> i64 {
         unsafe { (self.f)(self.data, args, locals, ret) }
     }
 }
 
-pub fn make_func<'a, F: Fn(*const i64, *mut i64, i64) -> i64 + 'a>(f: F) -> Func<'a> {
+pub(crate) fn make_func<'a, F: Fn(*const i64, *mut i64, i64) -> i64 + Send + Sync + 'a>(f: F) -> Func<'a> {
     #[inline(always)]
     unsafe fn invoke<F: Fn(*const i64, *mut i64, i64) -> i64>(
         data: *const (),
@@ -67,25 +93,62 @@ pub fn make_func<'a, F: Fn(*const i64, *mut i64, i64) -> i64 + 'a>(f: F) -> Func
         locals: *mut i64,
         ret: i64,
     ) -> i64 {
-        let f = std::mem::transmute::<_, &F>(data);
+        let f = &*(data as *const F);
         f(args, locals, ret)
     }
 
+    unsafe fn drop_data<F>(data: *const ()) {
+        drop(Box::from_raw(data as *mut F));
+    }
+
     Func {
         f: invoke::<F>,
         data: Box::into_raw(Box::new(f)) as _,
+        drop: drop_data::<F>,
         phantom: PhantomData,
     }
 }
 
 impl ClosureContinuations {
-    unsafe fn compile<'a>(
-        expr: &'a Expr,
-        cont: impl MaybeCont<'a> + 'a,
-    ) -> <Self as Vm>::Program<'a> {
+    unsafe fn compile<'a>(expr: &Expr, cont: impl MaybeCont<'a> + 'a) -> Func<'a> {
         // A stand-in for expressions that don't return anything
         const UNIT: i64 = 0;
 
+        if let Some(rewrite) = Rewrite::find(expr) {
+            return match rewrite {
+                Rewrite::AddOne { x } => Self::compile(
+                    x,
+                    make_func(move |args, locals, r| cont.cont(args, locals, r + 1)),
+                ),
+                Rewrite::SubOne { x } => Self::compile(
+                    x,
+                    make_func(move |args, locals, r| cont.cont(args, locals, r - 1)),
+                ),
+                Rewrite::AddLitr { x, y } => Self::compile(
+                    x,
+                    make_func(move |args, locals, r| cont.cont(args, locals, r + y)),
+                ),
+                Rewrite::AddArg1 { x } => Self::compile(
+                    x,
+                    make_func(move |args, locals, r| {
+                        cont.cont(args, locals, r + unsafe { *args.add(1) })
+                    }),
+                ),
+                Rewrite::AddAssign { local, y } => {
+                    let offset = -1 - local as isize;
+                    Self::compile(
+                        y,
+                        make_func(move |args, locals, r| {
+                            unsafe {
+                                *locals.offset(offset) += r;
+                            }
+                            cont.cont(args, locals, UNIT)
+                        }),
+                    )
+                }
+            };
+        }
+
         match expr {
             Expr::Litr(x) => {
                 let x = *x;
@@ -119,40 +182,16 @@ impl ClosureContinuations {
                     })
                 }
             },
-            Expr::Add(x, y) => match &**y {
-                Expr::Litr(1) => Self::compile(
-                    x,
-                    make_func(move |args, locals, r| cont.cont(args, locals, r + 1)),
-                ),
-                Expr::Litr(-1) => Self::compile(
-                    x,
-                    make_func(move |args, locals, r| cont.cont(args, locals, r - 1)),
-                ),
-
-                Expr::Litr(y) => {
-                    let y = *y;
-                    Self::compile(
-                        x,
-                        make_func(move |args, locals, r| cont.cont(args, locals, r + y)),
-                    )
-                }
-                Expr::Arg(1) => Self::compile(
+            Expr::Add(x, y) => {
+                let y = Self::compile(y, ());
+                Self::compile(
                     x,
                     make_func(move |args, locals, r| {
-                        cont.cont(args, locals, r + unsafe { *args.add(1) })
+                        let y = y.invoke(args, locals, 0);
+                        cont.cont(args, locals, r + y)
                     }),
-                ),
-                _ => {
-                    let y = Self::compile(y, ());
-                    Self::compile(
-                        x,
-                        make_func(move |args, locals, r| {
-                            let y = y.invoke(args, locals, 0);
-                            cont.cont(args, locals, r + y)
-                        }),
-                    )
-                }
-            },
+                )
+            }
             Expr::Let(rhs, then) => {
                 let then = Self::compile(
                     then,
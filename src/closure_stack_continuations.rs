@@ -1,54 +1,142 @@
 use super::*;
 use core::marker::PhantomData;
 
-pub struct Stack(*mut i64);
+pub struct Stack<M: StackMode> {
+    top: *mut i64,
+    limit: M::Limit,
+}
+
+impl<M: StackMode> Stack<M> {
+    unsafe fn new(buf: *mut Vec<i64>) -> Self {
+        Stack {
+            top: (*buf).as_mut_ptr(),
+            limit: M::limit(buf),
+        }
+    }
 
-impl Stack {
     #[inline(always)]
     unsafe fn push(&mut self, x: i64) {
-        self.0.write(x);
-        self.0 = self.0.add(1);
+        M::reserve(self);
+        self.top.write(x);
+        self.top = self.top.add(1);
     }
 
     #[inline(always)]
     unsafe fn pop(&mut self) -> i64 {
-        self.0 = self.0.sub(1);
-        self.0.read()
+        self.top = self.top.sub(1);
+        self.top.read()
     }
+}
+
+/// How the operand stack is kept in bounds.
+pub trait StackMode: Sized + 'static {
+    /// What a stack needs to know, beyond where its top is, to check a push.
+    type Limit: Copy;
+
+    /// # Safety
+    ///
+    /// `buf` must be valid for the whole execution, and only be accessed through the stack while it runs.
+    unsafe fn limit(buf: *mut Vec<i64>) -> Self::Limit;
+
+    /// Make room for one more value.
+    ///
+    /// # Safety
+    ///
+    /// `stack` must have been created by `Stack::new`.
+    unsafe fn reserve(stack: &mut Stack<Self>);
+}
+
+/// The stack is sized from the program's `max_stack` before execution, and pushes aren't checked. Programs must not
+/// need more than they claim.
+pub struct Presized;
 
-    unsafe fn set_offset(&mut self, offset: usize, x: i64) {
-        self.0.sub(offset).write(x);
+impl StackMode for Presized {
+    type Limit = ();
+
+    unsafe fn limit(_: *mut Vec<i64>) {}
+
+    #[inline(always)]
+    unsafe fn reserve(_: &mut Stack<Self>) {}
+}
+
+/// Every push checks that there's room on the stack (the guard), and grows it if there isn't. `max_stack` is only a
+/// hint, so this works for programs whose stack depth can't be bounded when they're compiled.
+pub struct Guarded;
+
+impl StackMode for Guarded {
+    type Limit = (*mut Vec<i64>, *mut i64); // (buffer, end)
+
+    unsafe fn limit(buf: *mut Vec<i64>) -> Self::Limit {
+        (buf, (*buf).as_mut_ptr().add((*buf).len()))
     }
 
-    unsafe fn get_offset(&self, offset: usize) -> i64 {
-        self.0.sub(offset).read()
+    #[inline(always)]
+    unsafe fn reserve(stack: &mut Stack<Self>) {
+        #[cold]
+        unsafe fn grow(stack: &mut Stack<Guarded>) {
+            let buf = &mut *stack.limit.0;
+            let len = buf.len();
+            buf.resize((len * 2).max(16), 0);
+            stack.top = buf.as_mut_ptr().add(len);
+            stack.limit.1 = buf.as_mut_ptr().add(buf.len());
+        }
+
+        if stack.top == stack.limit.1 {
+            grow(stack);
+        }
+    }
+}
+
+pub struct ClosureStackContinuations<M>(PhantomData<fn() -> M>);
+
+impl<M> ClosureStackContinuations<M> {
+    pub const fn new() -> Self {
+        Self(PhantomData)
     }
 }
 
-pub struct ClosureStackContinuations;
+impl<M> Default for ClosureStackContinuations<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-impl Vm for ClosureStackContinuations {
-    type Program<'a> = Func<'a>;
+impl<M: StackMode> Vm for ClosureStackContinuations<M> {
+    type Program<'a> = Frame<Func<'static, M>>;
+    type Context = (Vec<i64>, Vec<i64>); // (locals, stack)
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
-        unsafe { Self::compile(expr, ()) }
+        Frame {
+            code: unsafe { Self::compile(expr, ()) },
+            max_locals: expr.max_locals(),
+            max_stack: expr.max_stack(),
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
     }
 
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
-        let mut v = vec![0; 64];
-        let stack_raw = Box::into_raw(vec![0i64; 1024].into_boxed_slice());
-        let stack = Stack(stack_raw as _);
-        prog.invoke(args.as_ptr(), v.as_mut_ptr(), stack);
-        (*stack_raw)[0]
+    unsafe fn execute_in(
+        (locals, stack): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        reserve_slots(locals, prog.max_locals);
+        reserve_slots(stack, prog.max_stack);
+        let buf: *mut Vec<i64> = stack;
+        prog.code
+            .invoke(args.as_ptr(), locals.as_mut_ptr(), Stack::new(buf));
+        stack[0]
     }
 }
 
-trait MaybeCont<'a> {
+trait MaybeCont<'a, M: StackMode>: Send + Sync {
     #[inline(always)]
-    fn cont(&self, args: *const i64, locals: *mut i64, stack: Stack) -> Stack {
+    fn cont(&self, _args: *const i64, _locals: *mut i64, stack: Stack<M>) -> Stack<M> {
         stack
     }
-    fn map(self, f: impl FnOnce(Self) -> Func<'a>) -> Self
+    fn map(self, _f: impl FnOnce(Self) -> Func<'a, M>) -> Self
     where
         Self: Sized,
     {
@@ -56,13 +144,13 @@ trait MaybeCont<'a> {
     }
 }
 
-impl<'a> MaybeCont<'a> for () {}
-impl<'a> MaybeCont<'a> for Func<'a> {
+impl<'a, M: StackMode> MaybeCont<'a, M> for () {}
+impl<'a, M: StackMode> MaybeCont<'a, M> for Func<'a, M> {
     #[inline(always)]
-    fn cont(&self, args: *const i64, locals: *mut i64, stack: Stack) -> Stack {
+    fn cont(&self, args: *const i64, locals: *mut i64, stack: Stack<M>) -> Stack<M> {
         self.invoke(args, locals, stack)
     }
-    fn map(self, f: impl FnOnce(Self) -> Func<'a>) -> Self
+    fn map(self, f: impl FnOnce(Self) -> Func<'a, M>) -> Self
     where
         Self: Sized,
     {
@@ -73,55 +161,61 @@ impl<'a> MaybeCont<'a> for Func<'a> {
 // Sadly, rustc currently does a poor job of generating good vtable dispatch code for functions.
 // This is the solution: a custom wide pointer that uses a combination of inlining and transmutation to do the right
 // thing.
-pub struct Func<'a> {
-    f: unsafe fn(*const (), *const i64, *mut i64, Stack) -> Stack,
+pub struct Func<'a, M: StackMode> {
+    f: unsafe fn(*const (), *const i64, *mut i64, Stack<M>) -> Stack<M>,
     data: *const (),
+    // Frees `data`, which is type-erased
+    drop: unsafe fn(*const ()),
     phantom: PhantomData<&'a ()>,
 }
 
-impl<'a> Func<'a> {
+// SAFETY: `make_func` only accepts closures that are `Send + Sync`, and `data` is never mutated after construction.
+unsafe impl<'a, M: StackMode> Send for Func<'a, M> {}
+unsafe impl<'a, M: StackMode> Sync for Func<'a, M> {}
+
+impl<'a, M: StackMode> Drop for Func<'a, M> {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+impl<'a, M: StackMode> Func<'a, M> {
     #[inline(always)]
-    pub fn invoke(&self, args: *const i64, locals: *mut i64, stack: Stack) -> Stack {
+    pub(crate) fn invoke(&self, args: *const i64, locals: *mut i64, stack: Stack<M>) -> Stack<M> {
         unsafe { (self.f)(self.data, args, locals, stack) }
     }
 }
 
-pub fn make_func<'a, F: Fn(*const i64, *mut i64, Stack) -> Stack + 'a>(f: F) -> Func<'a> {
+pub(crate) fn make_func<'a, M, F>(f: F) -> Func<'a, M>
+where
+    M: StackMode,
+    F: Fn(*const i64, *mut i64, Stack<M>) -> Stack<M> + Send + Sync + 'a,
+{
     #[inline(always)]
-    unsafe fn invoke<F: Fn(*const i64, *mut i64, Stack) -> Stack>(
+    unsafe fn invoke<M: StackMode, F: Fn(*const i64, *mut i64, Stack<M>) -> Stack<M>>(
         data: *const (),
         args: *const i64,
         locals: *mut i64,
-        stack: Stack,
-    ) -> Stack {
-        let f = std::mem::transmute::<_, &F>(data);
+        stack: Stack<M>,
+    ) -> Stack<M> {
+        let f = &*(data as *const F);
         f(args, locals, stack)
     }
 
+    unsafe fn drop_data<F>(data: *const ()) {
+        drop(Box::from_raw(data as *mut F));
+    }
+
     Func {
-        f: invoke::<F>,
+        f: invoke::<M, F>,
         data: Box::into_raw(Box::new(f)) as _,
+        drop: drop_data::<F>,
         phantom: PhantomData,
     }
 }
 
-impl ClosureStackContinuations {
-    unsafe fn compile<'a>(
-        expr: &'a Expr,
-        cont: impl MaybeCont<'a> + 'a,
-    ) -> <Self as Vm>::Program<'a> {
-        // A stand-in for expressions that don't return anything
-        const UNIT: i64 = 0;
-
-        fn returns(expr: &Expr) -> bool {
-            match expr {
-                Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) | Expr::Add(_, _) => true,
-                Expr::Let(_, expr) => returns(expr),
-                Expr::Set(_, _) | Expr::While(_, _) => false,
-                Expr::Then(_, b) => returns(b),
-            }
-        }
-
+impl<M: StackMode> ClosureStackContinuations<M> {
+    unsafe fn compile<'a>(expr: &Expr, cont: impl MaybeCont<'a, M> + 'a) -> Func<'a, M> {
         match expr {
             Expr::Litr(x) => {
                 let x = *x;
@@ -267,7 +361,7 @@ impl ClosureStackContinuations {
             },
             Expr::While(pred, body) => {
                 let pred = Self::compile(pred, ());
-                let body_returns = returns(body);
+                let body_returns = body.returns();
                 let body = Self::compile(body, ());
                 make_func(move |args, locals, mut stack| {
                     loop {
@@ -286,7 +380,7 @@ impl ClosureStackContinuations {
             }
             Expr::Then(a, b) => {
                 let b = Self::compile(b, cont);
-                let a_returns = returns(a);
+                let a_returns = a.returns();
                 // TODO: Check if a returns, pop from stack if so
                 Self::compile(
                     a,
@@ -0,0 +1,262 @@
+use super::*;
+
+// `ClosureContinuations`, except continuations are called with `become` rather than an ordinary call in tail position.
+// A closure can't be the target of `become`, so instead of calling its continuation, each closure returns it (as a
+// `Next`) to the function that invoked it, which then tail-calls it. Tail calls are then guaranteed rather than left to
+// the optimiser, even in debug builds.
+pub struct ClosureTailCalls;
+
+impl Vm for ClosureTailCalls {
+    type Program<'a> = Frame<Func>;
+    type Context = Vec<i64>; // locals
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        Frame {
+            code: Self::compile_inner(expr, ()),
+            max_locals: expr.max_locals(),
+            max_stack: 0, // Intermediate values live on the hardware stack
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        Vec::new()
+    }
+
+    unsafe fn execute_in(
+        locals: &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        reserve_slots(locals, prog.max_locals);
+        prog.code.invoke(args.as_ptr(), locals.as_mut_ptr(), 0)
+    }
+}
+
+// What to do with the result of a closure
+pub enum Next {
+    // Pass it to a continuation, with the given locals
+    Tail(*const Func, *mut i64, i64),
+    // Return it, since there's no continuation
+    Return(i64),
+}
+
+trait MaybeCont: Send + Sync {
+    fn next(&self, locals: *mut i64, result: i64) -> Next;
+
+    fn map(self, _f: impl FnOnce(Self) -> Func) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+}
+
+impl MaybeCont for () {
+    #[inline(always)]
+    fn next(&self, _locals: *mut i64, result: i64) -> Next {
+        Next::Return(result)
+    }
+}
+
+impl MaybeCont for Func {
+    #[inline(always)]
+    fn next(&self, locals: *mut i64, result: i64) -> Next {
+        Next::Tail(self, locals, result)
+    }
+
+    fn map(self, f: impl FnOnce(Self) -> Func) -> Self {
+        f(self)
+    }
+}
+
+type FuncFn = unsafe fn(*const (), *const i64, *mut i64, i64) -> i64;
+
+// A type-erased closure, like `closure_continuations::Func`. `f` has the same signature for every closure, which is what
+// allows one closure's `f` to `become` another's.
+pub struct Func {
+    f: FuncFn,
+    data: *const (),
+    // Frees `data`, which is type-erased
+    drop: unsafe fn(*const ()),
+}
+
+// SAFETY: `make_func` only accepts closures that are `Send + Sync`, and `data` is never mutated after construction.
+unsafe impl Send for Func {}
+unsafe impl Sync for Func {}
+
+impl Drop for Func {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+impl Func {
+    /// Call the closure, and any continuations it passes its result to.
+    ///
+    /// # Safety
+    ///
+    /// `args` and `locals` must be valid for every argument and local the closure reads or writes.
+    #[inline(always)]
+    pub unsafe fn invoke(&self, args: *const i64, locals: *mut i64, ret: i64) -> i64 {
+        (self.f)(self.data, args, locals, ret)
+    }
+}
+
+fn make_func<F: Fn(*const i64, *mut i64, i64) -> Next + Send + Sync + 'static>(f: F) -> Func {
+    unsafe fn invoke<F: Fn(*const i64, *mut i64, i64) -> Next>(
+        data: *const (),
+        args: *const i64,
+        locals: *mut i64,
+        ret: i64,
+    ) -> i64 {
+        let f = &*(data as *const F);
+        match f(args, locals, ret) {
+            Next::Tail(cont, locals, ret) => {
+                let cont = &*cont;
+                become (cont.f)(cont.data, args, locals, ret)
+            }
+            Next::Return(ret) => ret,
+        }
+    }
+
+    unsafe fn drop_data<F>(data: *const ()) {
+        drop(Box::from_raw(data as *mut F));
+    }
+
+    Func {
+        f: invoke::<F>,
+        data: Box::into_raw(Box::new(f)) as _,
+        drop: drop_data::<F>,
+    }
+}
+
+impl ClosureTailCalls {
+    fn compile_inner(expr: &Expr, cont: impl MaybeCont + 'static) -> Func {
+        // A stand-in for expressions that don't return anything
+        const UNIT: i64 = 0;
+
+        match expr {
+            Expr::Litr(x) => {
+                let x = *x;
+                make_func(move |_, locals, _| cont.next(locals, x))
+            }
+            Expr::Arg(idx) => match idx {
+                0 => make_func(move |args, locals, _| cont.next(locals, unsafe { *args.add(0) })),
+                1 => make_func(move |args, locals, _| cont.next(locals, unsafe { *args.add(1) })),
+                _ => {
+                    let idx = *idx;
+                    make_func(move |args, locals, _| cont.next(locals, unsafe { *args.add(idx) }))
+                }
+            },
+            Expr::Get(local) => match local {
+                0 => {
+                    make_func(move |_, locals, _| cont.next(locals, unsafe { *locals.offset(-1) }))
+                }
+                1 => {
+                    make_func(move |_, locals, _| cont.next(locals, unsafe { *locals.offset(-2) }))
+                }
+                _ => {
+                    let offset = -1 - *local as isize;
+                    make_func(move |_, locals, _| {
+                        cont.next(locals, unsafe { *locals.offset(offset) })
+                    })
+                }
+            },
+            Expr::Add(x, y) => match &**y {
+                Expr::Litr(1) => {
+                    Self::compile_inner(x, make_func(move |_, locals, r| cont.next(locals, r + 1)))
+                }
+                Expr::Litr(-1) => {
+                    Self::compile_inner(x, make_func(move |_, locals, r| cont.next(locals, r - 1)))
+                }
+                Expr::Litr(y) => {
+                    let y = *y;
+                    Self::compile_inner(x, make_func(move |_, locals, r| cont.next(locals, r + y)))
+                }
+                Expr::Arg(1) => Self::compile_inner(
+                    x,
+                    make_func(move |args, locals, r| {
+                        cont.next(locals, r + unsafe { *args.add(1) })
+                    }),
+                ),
+                _ => {
+                    let y = Self::compile_inner(y, ());
+                    Self::compile_inner(
+                        x,
+                        make_func(move |args, locals, r| {
+                            let y = unsafe { y.invoke(args, locals, 0) };
+                            cont.next(locals, r + y)
+                        }),
+                    )
+                }
+            },
+            Expr::Let(rhs, then) => {
+                let then = Self::compile_inner(
+                    then,
+                    cont.map(|cont| {
+                        make_func(move |_, locals: *mut i64, r| {
+                            cont.next(unsafe { locals.offset(-1) }, r)
+                        })
+                    }),
+                );
+                Self::compile_inner(
+                    rhs,
+                    make_func(move |_, locals, r| {
+                        unsafe {
+                            locals.write(r);
+                        }
+                        Next::Tail(&then, unsafe { locals.add(1) }, 0)
+                    }),
+                )
+            }
+            Expr::Set(local, rhs) => match local {
+                0 => Self::compile_inner(
+                    rhs,
+                    make_func(move |_, locals, r| {
+                        unsafe {
+                            locals.offset(-1).write(r);
+                        }
+                        cont.next(locals, UNIT)
+                    }),
+                ),
+                1 => Self::compile_inner(
+                    rhs,
+                    make_func(move |_, locals, r| {
+                        unsafe {
+                            locals.offset(-2).write(r);
+                        }
+                        cont.next(locals, UNIT)
+                    }),
+                ),
+                _ => {
+                    let offset = -1 - *local as isize;
+                    Self::compile_inner(
+                        rhs,
+                        make_func(move |_, locals, r| {
+                            unsafe {
+                                locals.offset(offset).write(r);
+                            }
+                            cont.next(locals, UNIT)
+                        }),
+                    )
+                }
+            },
+            Expr::While(pred, body) => {
+                let pred = Self::compile_inner(pred, ());
+                let body = Self::compile_inner(body, ());
+                make_func(move |args, locals, _| {
+                    unsafe {
+                        while pred.invoke(args, locals, 0) > 0 {
+                            body.invoke(args, locals, 0);
+                        }
+                    }
+                    cont.next(locals, UNIT)
+                })
+            }
+            Expr::Then(a, b) => {
+                let b = Self::compile_inner(b, cont);
+                Self::compile_inner(a, make_func(move |_, locals, _| Next::Tail(&b, locals, 0)))
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use super::*;
+use crate::rewrite::Rewrite;
 use core::marker::PhantomData;
 
 // Sadly, rustc currently does a poor job of generating good vtable dispatch code for functions.
@@ -7,30 +8,47 @@ use core::marker::PhantomData;
 pub struct Func<'a> {
     f: unsafe fn(*const (), *const i64, *mut i64) -> i64,
     data: *const (),
+    // Frees `data`, which is type-erased
+    drop: unsafe fn(*const ()),
     phantom: PhantomData<&'a ()>,
 }
 
+// SAFETY: `make_func` only accepts closures that are `Send + Sync`, and `data` is never mutated after construction.
+unsafe impl<'a> Send for Func<'a> {}
+unsafe impl<'a> Sync for Func<'a> {}
+
+impl<'a> Drop for Func<'a> {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
 impl<'a> Func<'a> {
     #[inline(always)]
-    pub fn invoke(&self, args: *const i64, locals: *mut i64) -> i64 {
+    pub(crate) fn invoke(&self, args: *const i64, locals: *mut i64) -> i64 {
         unsafe { (self.f)(self.data, args, locals) }
     }
 }
 
-pub fn make_func<'a, F: Fn(*const i64, *mut i64) -> i64 + 'a>(f: F) -> Func<'a> {
+pub(crate) fn make_func<'a, F: Fn(*const i64, *mut i64) -> i64 + Send + Sync + 'a>(f: F) -> Func<'a> {
     #[inline(always)]
     unsafe fn invoke<F: Fn(*const i64, *mut i64) -> i64>(
         data: *const (),
         args: *const i64,
         locals: *mut i64,
     ) -> i64 {
-        let f = std::mem::transmute::<_, &F>(data);
+        let f = &*(data as *const F);
         f(args, locals)
     }
 
+    unsafe fn drop_data<F>(data: *const ()) {
+        drop(Box::from_raw(data as *mut F));
+    }
+
     Func {
         f: invoke::<F>,
         data: Box::into_raw(Box::new(f)) as _,
+        drop: drop_data::<F>,
         phantom: PhantomData,
     }
 }
@@ -38,12 +56,68 @@ pub fn make_func<'a, F: Fn(*const i64, *mut i64) -> i64 + 'a>(f: F) -> Func<'a>
 pub struct Closures;
 
 impl Vm for Closures {
-    type Program<'a> = Func<'a>;
+    type Program<'a> = Frame<Func<'static>>;
+    type Context = Vec<i64>; // locals
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
+        Frame {
+            code: Self::compile_inner(expr),
+            max_locals: expr.max_locals(),
+            max_stack: 0, // Intermediate values live on the hardware stack
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        Vec::new()
+    }
+
+    unsafe fn execute_in(
+        locals: &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        reserve_slots(locals, prog.max_locals);
+        prog.code.invoke(args.as_ptr(), locals.as_mut_ptr())
+    }
+}
+
+impl Closures {
+    fn compile_inner(expr: &Expr) -> Func<'static> {
         // A stand-in for expressions that don't return anything
         const UNIT: i64 = 0;
 
+        if let Some(rewrite) = Rewrite::find(expr) {
+            return match rewrite {
+                Rewrite::AddOne { x } => {
+                    let x = Self::compile_inner(x);
+                    make_func(move |args, locals| x.invoke(args, locals) + 1)
+                }
+                Rewrite::SubOne { x } => {
+                    let x = Self::compile_inner(x);
+                    make_func(move |args, locals| x.invoke(args, locals) - 1)
+                }
+                Rewrite::AddLitr { x, y } => {
+                    let x = Self::compile_inner(x);
+                    make_func(move |args, locals| x.invoke(args, locals) + y)
+                }
+                Rewrite::AddArg1 { x } => {
+                    let x = Self::compile_inner(x);
+                    make_func(move |args, locals| x.invoke(args, locals) + unsafe { *args.add(1) })
+                }
+                Rewrite::AddAssign { local, y } => {
+                    let y = Self::compile_inner(y);
+                    let offset = -1 - local as isize;
+                    make_func(move |args, locals| {
+                        let y = y.invoke(args, locals);
+                        unsafe {
+                            *locals.offset(offset) += y;
+                        }
+                        UNIT
+                    })
+                }
+            };
+        }
+
         match expr {
             Expr::Litr(x) => {
                 let x = *x;
@@ -65,45 +139,25 @@ impl Vm for Closures {
                     make_func(move |_, locals| unsafe { *locals.offset(offset) })
                 }
             },
-            Expr::Add(x, y) => match &**y {
-                Expr::Litr(1) => {
-                    let x = Self::compile(x);
-                    make_func(move |args, locals| x.invoke(args, locals) + 1)
-                }
-                Expr::Litr(-1) => {
-                    let x = Self::compile(x);
-                    make_func(move |args, locals| x.invoke(args, locals) - 1)
-                }
-                Expr::Litr(y) => {
-                    let x = Self::compile(x);
-                    let y = *y;
-                    make_func(move |args, locals| x.invoke(args, locals) + y)
-                }
-                Expr::Arg(1) => {
-                    let x = Self::compile(x);
-                    make_func(move |args, locals| x.invoke(args, locals) + unsafe { *args.add(1) })
-                }
-                _ => {
-                    let x = Self::compile(x);
-                    let y = Self::compile(y);
-                    make_func(move |args, locals| x.invoke(args, locals) + y.invoke(args, locals))
-                }
-            },
+            Expr::Add(x, y) => {
+                let x = Self::compile_inner(x);
+                let y = Self::compile_inner(y);
+                make_func(move |args, locals| x.invoke(args, locals) + y.invoke(args, locals))
+            }
             Expr::Let(rhs, then) => {
-                let rhs = Self::compile(rhs);
-                let then = Self::compile(then);
+                let rhs = Self::compile_inner(rhs);
+                let then = Self::compile_inner(then);
                 make_func(move |args, locals| {
                     let rhs = rhs.invoke(args, locals);
                     unsafe {
                         locals.write(rhs);
                     }
-                    let res = then.invoke(args, unsafe { locals.offset(1) });
-                    res
+                    then.invoke(args, unsafe { locals.offset(1) })
                 })
             }
             Expr::Set(local, rhs) => match local {
                 0 => {
-                    let rhs = Self::compile(rhs);
+                    let rhs = Self::compile_inner(rhs);
                     make_func(move |args, locals| {
                         let rhs = rhs.invoke(args, locals);
                         unsafe {
@@ -113,7 +167,7 @@ impl Vm for Closures {
                     })
                 }
                 1 => {
-                    let rhs = Self::compile(rhs);
+                    let rhs = Self::compile_inner(rhs);
                     make_func(move |args, locals| {
                         let rhs = rhs.invoke(args, locals);
                         unsafe {
@@ -123,7 +177,7 @@ impl Vm for Closures {
                     })
                 }
                 _ => {
-                    let rhs = Self::compile(rhs);
+                    let rhs = Self::compile_inner(rhs);
                     let offset = -1 - *local as isize;
                     make_func(move |args, locals| {
                         let rhs = rhs.invoke(args, locals);
@@ -135,8 +189,8 @@ impl Vm for Closures {
                 }
             },
             Expr::While(pred, body) => {
-                let pred = Self::compile(pred);
-                let body = Self::compile(body);
+                let pred = Self::compile_inner(pred);
+                let body = Self::compile_inner(body);
                 make_func(move |args, locals| {
                     while pred.invoke(args, locals) > 0 {
                         body.invoke(args, locals);
@@ -145,8 +199,8 @@ impl Vm for Closures {
                 })
             }
             Expr::Then(a, b) => {
-                let a = Self::compile(a);
-                let b = Self::compile(b);
+                let a = Self::compile_inner(a);
+                let b = Self::compile_inner(b);
                 make_func(move |args, locals| {
                     a.invoke(args, locals);
                     b.invoke(args, locals)
@@ -154,9 +208,4 @@ impl Vm for Closures {
             }
         }
     }
-
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
-        let mut v = vec![0; 64];
-        prog.invoke(args.as_ptr(), v.as_mut_ptr())
-    }
 }
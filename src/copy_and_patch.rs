@@ -0,0 +1,110 @@
+use super::*;
+use crate::{bytecode::Op, jit::Code};
+
+// Copy-and-patch compilation, as used by CPython's JIT. Each `Bytecode` op has a stencil: machine code compiled ahead of
+// time by rustc from `stencils/stencils.rs`, with holes left for its operands and for the stencils that follow it (see
+// `build.rs`). Compiling a program is then just a matter of copying the stencil for each op into executable memory and
+// patching the holes.
+//
+// Like `ReplicatedBytecode`, each op passes control directly to the next with the VM state in registers, but with no
+// dispatch at all: a stencil that ends by jumping to the next op simply falls through into it.
+pub struct CopyAndPatch;
+
+pub(crate) struct Stencil {
+    code: &'static [u8],
+    holes: &'static [Hole],
+}
+
+// A 64-bit absolute address in a stencil that gets patched with `value + addend`
+pub(crate) struct Hole {
+    offset: usize,
+    kind: HoleKind,
+    addend: i64,
+}
+
+pub(crate) enum HoleKind {
+    Operand,
+    Continue,
+    Jump,
+}
+
+mod stencils {
+    use super::*;
+    include!(concat!(env!("OUT_DIR"), "/stencils.rs"));
+}
+
+type StencilFn = unsafe extern "C" fn(*mut i64, *mut i64, *const i64) -> i64;
+
+impl Vm for CopyAndPatch {
+    type Program<'a> = Frame<Code>;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        let ops = Bytecode::compile(expr);
+
+        // (stencil, operand, jump target) for each op
+        let stencils = ops
+            .code
+            .iter()
+            .map(|op| match op {
+                Op::Litr(x) => (&stencils::LITR, *x, None),
+                Op::Arg(idx) => (&stencils::ARG, 8 * *idx as i64, None),
+                Op::Get(local) => (&stencils::GET, -8 * (*local as i64 + 1), None),
+                Op::Add => (&stencils::ADD, 0, None),
+                Op::PushLocal => (&stencils::PUSH_LOCAL, 0, None),
+                Op::PopLocal => (&stencils::POP_LOCAL, 0, None),
+                Op::SetLocal(local) => (&stencils::SET_LOCAL, -8 * (*local as i64 + 1), None),
+                Op::Pop => (&stencils::POP, 0, None),
+                Op::JmpZN(goto) => (&stencils::JMP_ZN, 0, Some(*goto)),
+                Op::Jmp(goto) => (&stencils::JMP, 0, Some(*goto)),
+                Op::Ret => (&stencils::RET, 0, None),
+            })
+            .collect::<Vec<_>>();
+
+        let mut positions = Vec::with_capacity(stencils.len() + 1);
+        let mut len = 0;
+        for (stencil, ..) in &stencils {
+            positions.push(len);
+            len += stencil.code.len();
+        }
+        positions.push(len);
+
+        let code = Code::with(len, |base, code| {
+            for (i, (stencil, operand, goto)) in stencils.iter().enumerate() {
+                let at = positions[i];
+                code[at..at + stencil.code.len()].copy_from_slice(stencil.code);
+                for hole in stencil.holes {
+                    let value = match hole.kind {
+                        HoleKind::Operand => *operand,
+                        HoleKind::Continue => (base + positions[i + 1]) as i64,
+                        HoleKind::Jump => (base + positions[goto.unwrap()]) as i64,
+                    };
+                    let at = at + hole.offset;
+                    code[at..at + 8]
+                        .copy_from_slice(&value.wrapping_add(hole.addend).to_le_bytes());
+                }
+            }
+        });
+
+        Frame {
+            code,
+            max_locals: ops.max_locals,
+            max_stack: ops.max_stack,
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
+    }
+
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        reserve_slots(stack, prog.max_stack);
+        reserve_slots(locals, prog.max_locals);
+        let f = core::mem::transmute::<*const u8, StencilFn>(prog.code.as_ptr());
+        f(stack.as_mut_ptr(), locals.as_mut_ptr(), args.as_ptr())
+    }
+}
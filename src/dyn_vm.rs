@@ -0,0 +1,95 @@
+use super::*;
+use crate::closure_stack_continuations::{Guarded, Presized};
+use core::marker::PhantomData;
+
+/// An object-safe counterpart to [`Vm`], allowing a backend to be selected at runtime.
+pub trait DynVm: Sync {
+    fn compile_dyn<'a>(&self, expr: &'a Expr) -> Box<dyn CompiledProgram + 'a>;
+}
+
+/// A type-erased program produced by [`DynVm::compile_dyn`].
+pub trait CompiledProgram: Send + Sync {
+    /// Execute the program with a fresh context.
+    ///
+    /// # Safety
+    ///
+    /// `args` must hold a value for every argument the program reads, as for [`Vm::execute`].
+    unsafe fn execute(&self, args: &[i64]) -> i64;
+
+    /// Execute the program once for each set of arguments, writing the results to `out`.
+    ///
+    /// # Safety
+    ///
+    /// As for `execute`, for every set of arguments in `args`. Panics if `args` and `out` differ in length.
+    unsafe fn execute_batch(&self, args: &[&[i64]], out: &mut [i64]);
+}
+
+struct Compiled<'a, V: Vm>(V::Program<'a>, PhantomData<fn() -> V>);
+
+impl<'a, V: Vm> CompiledProgram for Compiled<'a, V> {
+    unsafe fn execute(&self, args: &[i64]) -> i64 {
+        V::execute(&self.0, args)
+    }
+
+    unsafe fn execute_batch(&self, args: &[&[i64]], out: &mut [i64]) {
+        V::execute_batch(&self.0, args, out)
+    }
+}
+
+impl<V: Vm + Sync + 'static> DynVm for V {
+    fn compile_dyn<'a>(&self, expr: &'a Expr) -> Box<dyn CompiledProgram + 'a> {
+        Box::new(Compiled::<V>(V::compile(expr), PhantomData))
+    }
+}
+
+static BACKENDS: &[(&str, &dyn DynVm)] = &[
+    ("walker", &Walker),
+    ("bytecode", &Bytecode),
+    ("stack_cached_bytecode", &StackCachedBytecode),
+    ("register_bytecode", &RegisterBytecode),
+    ("threaded_bytecode", &ThreadedBytecode),
+    ("replicated_bytecode", &ReplicatedBytecode),
+    ("super_bytecode", &SuperBytecode),
+    ("tracing_bytecode", &TracingBytecode),
+    ("ssa_bytecode", &Ssa::<Bytecode>::new()),
+    ("ssa_closures", &Ssa::<Closures>::new()),
+    ("closures", &Closures),
+    ("stack_closures", &StackClosures),
+    ("tape_closures", &TapeClosures),
+    ("register_closures", &RegisterClosures::<2>),
+    ("bytecode_closures", &BytecodeClosures),
+    ("tape_continuations", &TapeContinuations),
+    ("tape_tail_calls", &TapeTailCalls),
+    ("closure_continuations", &ClosureContinuations),
+    (
+        "closure_stack_continuations",
+        &ClosureStackContinuations::<Presized>::new(),
+    ),
+    (
+        "closure_stack_continuations_guarded",
+        &ClosureStackContinuations::<Guarded>::new(),
+    ),
+    ("closure_tail_calls", &ClosureTailCalls),
+    ("simd_closures_4", &SimdClosures::<4>),
+    ("simd_closures_8", &SimdClosures::<8>),
+    ("simd_closures_16", &SimdClosures::<16>),
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    ("jit", &Jit),
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    ("copy_and_patch", &CopyAndPatch),
+    #[cfg(unix)]
+    ("rustc_aot", &RustcAot),
+    #[cfg(unix)]
+    ("c_aot", &CAot),
+    ("tiered", &Tiered::<Walker, ClosureContinuations, 64>::new()),
+];
+
+/// Every backend in the crate, keyed by the name used in the benchmark table.
+pub fn backends() -> &'static [(&'static str, &'static dyn DynVm)] {
+    BACKENDS
+}
+
+/// Look up a backend by name.
+pub fn backend(name: &str) -> Option<&'static dyn DynVm> {
+    BACKENDS.iter().find(|(n, _)| *n == name).map(|(_, vm)| *vm)
+}
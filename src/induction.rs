@@ -0,0 +1,132 @@
+use super::*;
+use crate::builder::*;
+
+// Induction variable analysis: recognise counted loops whose every assignment is affine in the trip count, and replace
+// them with closed-form arithmetic. For example,
+//
+//     while count > 0 { total = total + args[1]; count = count + -1 }
+//
+// runs `count` times (if `count` is positive), so it leaves `total + args[1] * count` in `total` and `0` in `count`.
+//
+// `Expr` has no multiplication, so the closed form multiplies by binary decomposition instead, using additions only:
+// the trip count is split into powers of two, largest first, which takes a number of steps logarithmic in the trip
+// count rather than linear in it.
+
+/// A loop that `closed_form` can replace: `while counter > 0 { ... }`, where the body decrements the counter by one
+/// and adds a loop-invariant step to every other local it assigns.
+pub struct InductionLoop<'a> {
+    /// The local that counts down the iterations.
+    pub counter: LocalOffset,
+    /// Each other local assigned by the loop, with the amount added to it per iteration.
+    pub steps: Vec<(LocalOffset, &'a Expr)>,
+}
+
+impl<'a> InductionLoop<'a> {
+    /// Recognise a loop whose assignments are all affine in its trip count.
+    pub fn find(expr: &'a Expr) -> Option<Self> {
+        let Expr::While(pred, body) = expr else {
+            return None;
+        };
+        let Expr::Get(counter) = **pred else {
+            return None;
+        };
+
+        let mut updates = Vec::new();
+        statements(body, &mut updates)?;
+        for (i, (local, _)) in updates.iter().enumerate() {
+            if updates[..i].iter().any(|(other, _)| other == local) {
+                return None;
+            }
+        }
+        // Steps are evaluated once, up front, so they mustn't depend on anything the loop changes
+        if !updates.iter().all(|(_, step)| is_invariant(step, &updates)) {
+            return None;
+        }
+
+        let (counters, steps) = updates
+            .into_iter()
+            .partition::<Vec<_>, _>(|(local, _)| *local == counter);
+        match counters[..] {
+            [(_, Expr::Litr(-1))] => Some(InductionLoop { counter, steps }),
+            _ => None,
+        }
+    }
+}
+
+// Collect the updates made by a body that only consists of `local = local + step`s
+fn statements<'a>(body: &'a Expr, out: &mut Vec<(LocalOffset, &'a Expr)>) -> Option<()> {
+    match body {
+        Expr::Then(a, b) => {
+            statements(a, out)?;
+            statements(b, out)
+        }
+        Expr::Set(local, rhs) => match &**rhs {
+            Expr::Add(x, step) | Expr::Add(step, x) if **x == Expr::Get(*local) => {
+                out.push((*local, step));
+                Some(())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Whether a step has the same value on every iteration
+fn is_invariant(step: &Expr, updates: &[(LocalOffset, &Expr)]) -> bool {
+    match step {
+        Expr::Litr(_) | Expr::Arg(_) => true,
+        Expr::Get(local) => updates.iter().all(|(updated, _)| updated != local),
+        Expr::Add(x, y) => is_invariant(x, updates) && is_invariant(y, updates),
+        Expr::Let(_, _) | Expr::Set(_, _) | Expr::While(_, _) | Expr::Then(_, _) => false,
+    }
+}
+
+/// Replace a loop recognised by `InductionLoop::find` with closed-form arithmetic. Like the loop, the result returns
+/// nothing.
+pub fn closed_form(lp: &InductionLoop) -> Expr {
+    // The counter ends at zero if the loop ran at all, and is left alone otherwise
+    let counter = lp.counter;
+    let reset = while_(get(counter), set(counter, litr(0)));
+
+    let updates = lp.steps.iter().rev().fold(reset, |rest, &(local, step)| {
+        // let s0 = step in (let r = counter in (let acc = 0 in (
+        //     while r > 0 {
+        //         // The largest power of two, p = -np, that's no more than r, along with s = s0 * p
+        //         let np = -1 in (let s = s0 in (
+        //             while r + np + np + 1 > 0 { np = np + np; s = s + s };
+        //             r = r + np;
+        //             acc = acc + s
+        //         ))
+        //     };
+        //     local = local + acc
+        // )))
+        let multiply = while_(
+            get(1),
+            let_(
+                litr(-1),
+                let_(
+                    get(3),
+                    then(
+                        while_(
+                            add(add(add(get(3), get(1)), get(1)), litr(1)),
+                            then(set(1, add(get(1), get(1))), set(0, add(get(0), get(0)))),
+                        ),
+                        then(set(3, add(get(3), get(1))), set(2, add(get(2), get(0)))),
+                    ),
+                ),
+            ),
+        );
+        let update = let_(
+            Box::new(step.clone()),
+            let_(
+                get(counter + 1),
+                let_(
+                    litr(0),
+                    then(multiply, set(local + 3, add(get(local + 3), get(0)))),
+                ),
+            ),
+        );
+        then(update, rest)
+    });
+    *updates
+}
@@ -0,0 +1,277 @@
+use super::*;
+use core::ffi::c_void;
+
+// A template JIT, emitting x86-64 machine code for each node directly. Every expression leaves its result in `rax`.
+// Locals live in the native stack frame, addressed relative to `rbp`, and the left operand of an `Add` is spilled to the
+// native stack while the right operand is evaluated.
+//
+// The generated code is a System V function taking a pointer to the arguments (kept in `rdi` throughout) and returning
+// the result.
+pub struct Jit;
+
+type JitFn = unsafe extern "C" fn(*const i64) -> i64;
+
+// Executable memory holding a compiled program
+pub struct Code {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// SAFETY: The code is never written to once it's executable
+unsafe impl Send for Code {}
+unsafe impl Sync for Code {}
+
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+const PROT_EXEC: i32 = 0x4;
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+        -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+impl Code {
+    pub(crate) fn new(bytes: &[u8]) -> Self {
+        Self::with(bytes.len(), |_, code| code.copy_from_slice(bytes))
+    }
+
+    // Map `len` bytes of code, filled in by `fill` given the address it will live at
+    pub(crate) fn with(len: usize, fill: impl FnOnce(usize, &mut [u8])) -> Self {
+        unsafe {
+            let ptr = mmap(
+                core::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(ptr, MAP_FAILED, "failed to map memory for code");
+            fill(
+                ptr as usize,
+                core::slice::from_raw_parts_mut(ptr as *mut u8, len),
+            );
+            // Never writable and executable at the same time
+            assert_eq!(
+                mprotect(ptr, len, PROT_READ | PROT_EXEC),
+                0,
+                "failed to make code executable"
+            );
+            Self { ptr, len }
+        }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        self.ptr as *const u8
+    }
+}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.ptr, self.len);
+        }
+    }
+}
+
+// Encodings of the handful of instructions that the JIT needs
+struct Asm {
+    code: Vec<u8>,
+}
+
+impl Asm {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_i32(&mut self, x: i32) {
+        self.emit(&x.to_le_bytes());
+    }
+
+    // The `rbp`-relative displacement of a local, given its depth from the bottom of the locals
+    fn local_disp(depth: usize) -> i32 {
+        -8 * (depth as i32 + 1)
+    }
+
+    fn mov_rax_imm(&mut self, x: i64) {
+        if let Ok(x) = i32::try_from(x) {
+            self.emit(&[0x48, 0xC7, 0xC0]); // mov rax, imm32 (sign-extended)
+            self.emit_i32(x);
+        } else {
+            self.emit(&[0x48, 0xB8]); // mov rax, imm64
+            self.emit(&x.to_le_bytes());
+        }
+    }
+
+    fn mov_rax_arg(&mut self, idx: usize) {
+        self.emit(&[0x48, 0x8B, 0x87]); // mov rax, [rdi + disp32]
+        self.emit_i32(8 * idx as i32);
+    }
+
+    fn mov_rax_local(&mut self, depth: usize) {
+        self.emit(&[0x48, 0x8B, 0x85]); // mov rax, [rbp + disp32]
+        self.emit_i32(Self::local_disp(depth));
+    }
+
+    fn mov_local_rax(&mut self, depth: usize) {
+        self.emit(&[0x48, 0x89, 0x85]); // mov [rbp + disp32], rax
+        self.emit_i32(Self::local_disp(depth));
+    }
+
+    fn add_rax_local(&mut self, depth: usize) {
+        self.emit(&[0x48, 0x03, 0x85]); // add rax, [rbp + disp32]
+        self.emit_i32(Self::local_disp(depth));
+    }
+
+    fn add_rax_arg(&mut self, idx: usize) {
+        self.emit(&[0x48, 0x03, 0x87]); // add rax, [rdi + disp32]
+        self.emit_i32(8 * idx as i32);
+    }
+
+    fn add_rax_imm(&mut self, x: i32) {
+        self.emit(&[0x48, 0x05]); // add rax, imm32
+        self.emit_i32(x);
+    }
+
+    fn push_rax(&mut self) {
+        self.emit(&[0x50]); // push rax
+    }
+
+    fn pop_add_rax(&mut self) {
+        self.emit(&[0x59]); // pop rcx
+        self.emit(&[0x48, 0x01, 0xC8]); // add rax, rcx
+    }
+
+    // Move `rsp` down by `size` bytes. A frame bigger than a page is probed a page at a time on the way down, so that
+    // the guard page below the stack is always touched rather than skipped over.
+    fn reserve_frame(&mut self, size: i32) {
+        const PAGE: i32 = 4096;
+        let mut rest = size;
+        if size > PAGE {
+            self.emit(&[0xB9]); // mov ecx, imm32
+            self.emit_i32(size / PAGE);
+            self.emit(&[0x48, 0x81, 0xEC]); // sub rsp, imm32
+            self.emit_i32(PAGE);
+            self.emit(&[0x48, 0x85, 0x24, 0x24]); // test [rsp], rsp
+            self.emit(&[0xFF, 0xC9]); // dec ecx
+            self.emit(&[0x75, 0xF1]); // jnz rel8, back to the `sub`
+            rest = size % PAGE;
+        }
+        self.emit(&[0x48, 0x81, 0xEC]); // sub rsp, imm32
+        self.emit_i32(rest);
+    }
+
+    fn unit(&mut self) {
+        self.emit(&[0x31, 0xC0]); // xor eax, eax
+    }
+
+    // Emits a `jle` with a placeholder target, returning the position of the target to fix up
+    fn jle_if_rax_not_positive(&mut self) -> usize {
+        self.emit(&[0x48, 0x85, 0xC0]); // test rax, rax
+        self.emit(&[0x0F, 0x8E]); // jle rel32
+        let fixup = self.code.len();
+        self.emit_i32(0); // Will be fixed up
+        fixup
+    }
+
+    fn jmp(&mut self, target: usize) {
+        self.emit(&[0xE9]); // jmp rel32
+        self.emit_i32(target as i32 - (self.code.len() as i32 + 4));
+    }
+
+    // Point the rel32 at `fixup` at the current position
+    fn fix_up(&mut self, fixup: usize) {
+        let rel = self.code.len() as i32 - (fixup as i32 + 4);
+        self.code[fixup..fixup + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+}
+
+impl Vm for Jit {
+    type Program<'a> = Frame<Code>;
+    type Context = (); // Everything lives on the native stack
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        // `depth` is the number of locals in scope
+        fn compile_inner(asm: &mut Asm, expr: &Expr, depth: usize) {
+            match expr {
+                Expr::Litr(x) => asm.mov_rax_imm(*x),
+                Expr::Arg(idx) => asm.mov_rax_arg(*idx),
+                Expr::Get(local) => asm.mov_rax_local(depth - local - 1),
+                Expr::Add(x, y) => {
+                    compile_inner(asm, x, depth);
+                    // Operands that don't need evaluating can be added straight from memory
+                    match &**y {
+                        Expr::Litr(y) if i32::try_from(*y).is_ok() => asm.add_rax_imm(*y as i32),
+                        Expr::Arg(idx) => asm.add_rax_arg(*idx),
+                        Expr::Get(local) => asm.add_rax_local(depth - local - 1),
+                        _ => {
+                            asm.push_rax();
+                            compile_inner(asm, y, depth);
+                            asm.pop_add_rax();
+                        }
+                    }
+                }
+                Expr::Let(rhs, then) => {
+                    compile_inner(asm, rhs, depth);
+                    asm.mov_local_rax(depth);
+                    compile_inner(asm, then, depth + 1);
+                }
+                Expr::Set(local, rhs) => {
+                    compile_inner(asm, rhs, depth);
+                    asm.mov_local_rax(depth - local - 1);
+                    asm.unit();
+                }
+                Expr::While(pred, body) => {
+                    let start = asm.code.len();
+                    compile_inner(asm, pred, depth);
+                    let exit_fixup = asm.jle_if_rax_not_positive();
+                    compile_inner(asm, body, depth);
+                    asm.jmp(start);
+                    asm.fix_up(exit_fixup);
+                    asm.unit();
+                }
+                Expr::Then(a, b) => {
+                    compile_inner(asm, a, depth);
+                    compile_inner(asm, b, depth);
+                }
+            }
+        }
+
+        let max_locals = expr.max_locals();
+        let mut asm = Asm { code: Vec::new() };
+
+        // Prologue: set up a frame with room for every local, keeping the stack 16-byte aligned
+        let frame_size = i32::try_from(max_locals)
+            .ok()
+            .and_then(|n| n.checked_mul(8)?.checked_add(15))
+            .map(|size| size & !15)
+            .expect("too many locals for a stack frame");
+        asm.emit(&[0x55]); // push rbp
+        asm.emit(&[0x48, 0x89, 0xE5]); // mov rbp, rsp
+        asm.reserve_frame(frame_size);
+
+        compile_inner(&mut asm, expr, 0);
+
+        // Epilogue
+        asm.emit(&[0xC9]); // leave
+        asm.emit(&[0xC3]); // ret
+
+        Frame {
+            code: Code::new(&asm.code),
+            max_locals,
+            max_stack: 0, // Intermediate values live on the native stack
+        }
+    }
+
+    fn new_context() -> Self::Context {}
+
+    unsafe fn execute_in(_: &mut Self::Context, prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+        let f = core::mem::transmute::<*const u8, JitFn>(prog.code.as_ptr());
+        f(args.as_ptr())
+    }
+}
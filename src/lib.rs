@@ -1,27 +1,66 @@
-#![feature(let_chains)]
+#![feature(explicit_tail_calls, portable_simd)]
+// `explicit_tail_calls` is still marked incomplete, but `become` is exactly what `*_tail_calls` need
+#![allow(incomplete_features)]
 
+#[doc(hidden)]
+pub mod builder;
 pub mod bytecode;
 pub mod bytecode_closures;
+#[cfg(unix)]
+pub mod c_aot;
 pub mod closure_continuations;
 pub mod closure_stack_continuations;
+pub mod closure_tail_calls;
 pub mod closures;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub mod copy_and_patch;
+pub mod dyn_vm;
+pub mod induction;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub mod jit;
+pub mod optimize;
+pub mod register_bytecode;
 pub mod register_closures;
+pub mod rewrite;
+#[cfg(unix)]
+pub mod rustc_aot;
+pub mod simd_closures;
+pub mod specialize;
+pub mod ssa;
+pub mod stack_cached_bytecode;
 pub mod stack_closures;
+pub mod super_bytecode;
 pub mod tape_closures;
 pub mod tape_continuations;
+pub mod tape_tail_calls;
+pub mod threaded_bytecode;
+pub mod tiered;
+pub mod tracing_bytecode;
 pub mod walker;
 
 pub use crate::{
     bytecode::Bytecode, bytecode_closures::BytecodeClosures,
     closure_continuations::ClosureContinuations,
-    closure_stack_continuations::ClosureStackContinuations, closures::Closures,
-    register_closures::RegisterClosures, stack_closures::StackClosures,
-    tape_closures::TapeClosures, tape_continuations::TapeContinuations, walker::Walker,
+    closure_stack_continuations::ClosureStackContinuations, closure_tail_calls::ClosureTailCalls,
+    closures::Closures, register_bytecode::RegisterBytecode, register_closures::RegisterClosures,
+    simd_closures::SimdClosures, ssa::Ssa, stack_cached_bytecode::StackCachedBytecode,
+    stack_closures::StackClosures, super_bytecode::SuperBytecode, tape_closures::TapeClosures,
+    tape_continuations::TapeContinuations, tape_tail_calls::TapeTailCalls, tiered::Tiered,
+    tracing_bytecode::TracingBytecode, walker::Walker,
 };
+#[cfg(unix)]
+pub use crate::{c_aot::CAot, rustc_aot::RustcAot};
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub use crate::{copy_and_patch::CopyAndPatch, jit::Jit};
+pub use dyn_vm::{backend, backends, CompiledProgram, DynVm};
+pub use optimize::{optimize, optimize_with};
+pub use specialize::specialize;
+pub use threaded_bytecode::{ReplicatedBytecode, ThreadedBytecode};
 
 // Relative to the top of the locals stack
 type LocalOffset = usize;
 
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Expr {
     Litr(i64),                   // i64
     Arg(usize),                  // i64
@@ -33,11 +72,111 @@ pub enum Expr {
     Then(Box<Expr>, Box<Expr>),  // ? -> ?
 }
 
+impl Expr {
+    /// Whether evaluating the expression produces a value, rather than just having effects.
+    pub fn returns(&self) -> bool {
+        match self {
+            Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) | Expr::Add(_, _) => true,
+            Expr::Let(_, then) => then.returns(),
+            Expr::Set(_, _) | Expr::While(_, _) => false,
+            Expr::Then(_, b) => b.returns(),
+        }
+    }
+
+    /// The maximum number of locals that are live at any one point during evaluation.
+    pub fn max_locals(&self) -> usize {
+        match self {
+            Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => 0,
+            Expr::Add(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+                x.max_locals().max(y.max_locals())
+            }
+            Expr::Let(rhs, then) => rhs.max_locals().max(1 + then.max_locals()),
+            Expr::Set(_, rhs) => rhs.max_locals(),
+        }
+    }
+
+    /// The maximum number of intermediate values that a stack machine (with locals kept elsewhere) needs to hold at any
+    /// one point during evaluation.
+    pub fn max_stack(&self) -> usize {
+        match self {
+            Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => 1,
+            Expr::Add(x, y) => x.max_stack().max(1 + y.max_stack()),
+            Expr::Let(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+                x.max_stack().max(y.max_stack())
+            }
+            Expr::Set(_, rhs) => rhs.max_stack(),
+        }
+    }
+}
+
+/// A compiled program, along with the buffer sizes it needs to execute.
+pub struct Frame<P> {
+    pub code: P,
+    pub max_locals: usize,
+    pub max_stack: usize,
+}
+
+// Grow a fixed-size buffer so that it can hold at least `len` values.
+// Contexts may be shared between programs, so this guard runs on every execution rather than trusting the context to
+// have been created for the program being run.
+fn reserve_slots<T: Clone + Default>(buf: &mut Vec<T>, len: usize) {
+    if buf.len() < len {
+        buf.resize(len, T::default());
+    }
+}
+
 pub trait Vm {
-    type Program<'a>;
+    // Programs can be shared between threads, so one compilation can be executed concurrently
+    type Program<'a>: Send + Sync;
+    // Scratch state (stacks, locals, etc.) that can be reused across executions to avoid per-call allocation
+    type Context;
 
     fn compile(expr: &Expr) -> Self::Program<'_>;
 
-    // SAFETY: Program must be well-formed.
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64;
+    fn new_context() -> Self::Context;
+
+    /// Execute a program, using `ctx` for scratch memory. A context can be reused across executions, and across
+    /// programs compiled by the same backend, since it grows to fit whichever program it's given.
+    ///
+    /// # Safety
+    ///
+    /// `prog` must have been produced by `Self::compile` from a well-formed `Expr`, and `args` must hold a value for
+    /// every argument the program reads, since arguments aren't bounds-checked.
+    unsafe fn execute_in(ctx: &mut Self::Context, prog: &Self::Program<'_>, args: &[i64]) -> i64;
+
+    /// Execute a program with a fresh context.
+    ///
+    /// # Safety
+    ///
+    /// As for `execute_in`: `prog` must come from `Self::compile`, and `args` must cover every argument it reads.
+    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+        Self::execute_in(&mut Self::new_context(), prog, args)
+    }
+
+    /// Execute the program once for each set of arguments, writing the results to `out`, using `ctx` for scratch
+    /// memory.
+    ///
+    /// # Safety
+    ///
+    /// As for `execute_in`, for every set of arguments in `args`. Panics if `args` and `out` differ in length.
+    unsafe fn execute_batch_in(
+        ctx: &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[&[i64]],
+        out: &mut [i64],
+    ) {
+        assert_eq!(args.len(), out.len());
+        for (args, out) in args.iter().zip(out) {
+            *out = Self::execute_in(ctx, prog, args);
+        }
+    }
+
+    /// Execute the program once for each set of arguments with a fresh context, writing the results to `out`.
+    ///
+    /// # Safety
+    ///
+    /// As for `execute_batch_in`.
+    unsafe fn execute_batch(prog: &Self::Program<'_>, args: &[&[i64]], out: &mut [i64]) {
+        Self::execute_batch_in(&mut Self::new_context(), prog, args, out)
+    }
 }
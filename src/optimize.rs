@@ -0,0 +1,202 @@
+use super::*;
+use crate::induction::{closed_form, InductionLoop};
+
+// Expression-level optimisations, applied before a program reaches any backend. Each pass rewrites single nodes, applied
+// bottom-up so that a node's children are already rewritten, and `optimize` runs the passes until nothing changes, since
+// one pass can expose work for another (removing an unused `Let` can leave a side-effect-free `Then`, for instance).
+//
+// Every rewrite keeps whether a node returns a value, which the stack-based backends rely on.
+
+/// A rewrite of a single node.
+pub type Pass = fn(Expr) -> Expr;
+
+/// The passes run by `optimize`, in order.
+pub const PASSES: &[(&str, Pass)] = &[
+    ("fold_constants", fold_constants),
+    ("remove_identities", remove_identities),
+    ("remove_dead_loops", remove_dead_loops),
+    ("remove_pure_thens", remove_pure_thens),
+    ("remove_unused_lets", remove_unused_lets),
+];
+
+/// Which optional optimisations `optimize_with` applies.
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// Replace counted loops with closed-form arithmetic, where `induction` can. This removes the very work that the
+    /// benchmarks measure the dispatch of, so it can be turned off to compare techniques on the loop itself.
+    pub closed_form_loops: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            closed_form_loops: true,
+        }
+    }
+}
+
+/// Optimise a program with every optimisation. The result can be compiled by any backend, and gives the same results
+/// as the original.
+pub fn optimize(expr: &Expr) -> Expr {
+    optimize_with(expr, Options::default())
+}
+
+/// Optimise a program, with the optional optimisations chosen by `options`.
+pub fn optimize_with(expr: &Expr, options: Options) -> Expr {
+    let mut expr = expr.clone();
+    loop {
+        let mut next = PASSES
+            .iter()
+            .fold(expr.clone(), |expr, (_, pass)| bottom_up(expr, *pass));
+        if options.closed_form_loops {
+            next = bottom_up(next, close_loops);
+        }
+        if next == expr {
+            return expr;
+        }
+        expr = next;
+    }
+}
+
+/// Apply a rewrite to every node of an expression, children first.
+pub fn bottom_up(expr: Expr, f: Pass) -> Expr {
+    let go = |x: Box<Expr>| Box::new(bottom_up(*x, f));
+    f(match expr {
+        Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => expr,
+        Expr::Add(x, y) => Expr::Add(go(x), go(y)),
+        Expr::Let(rhs, then) => Expr::Let(go(rhs), go(then)),
+        Expr::Set(local, rhs) => Expr::Set(local, go(rhs)),
+        Expr::While(pred, body) => Expr::While(go(pred), go(body)),
+        Expr::Then(a, b) => Expr::Then(go(a), go(b)),
+    })
+}
+
+/// `Add(Litr(x), Litr(y))` becomes `Litr(x + y)`.
+pub fn fold_constants(expr: Expr) -> Expr {
+    match expr {
+        Expr::Add(x, y) => match (*x, *y) {
+            (Expr::Litr(x), Expr::Litr(y)) => Expr::Litr(x.wrapping_add(y)),
+            (x, y) => Expr::Add(Box::new(x), Box::new(y)),
+        },
+        expr => expr,
+    }
+}
+
+/// `Add(x, Litr(0))` and `Add(Litr(0), x)` become `x`.
+pub fn remove_identities(expr: Expr) -> Expr {
+    match expr {
+        Expr::Add(x, y) => match (*x, *y) {
+            (x, Expr::Litr(0)) | (Expr::Litr(0), x) => x,
+            (x, y) => Expr::Add(Box::new(x), Box::new(y)),
+        },
+        expr => expr,
+    }
+}
+
+/// Loops whose predicate is a literal that's not positive never run. They're dropped from `Then`s, and anywhere else,
+/// their body is.
+pub fn remove_dead_loops(expr: Expr) -> Expr {
+    match expr {
+        Expr::While(pred, _) if is_dead_loop_pred(&pred) => nothing(),
+        Expr::Then(a, b) if is_dead_loop(&a) => *b,
+        // The loop stands in for the result of the `Then`, so it can only go if `a` doesn't return anything either
+        Expr::Then(a, b) if is_dead_loop(&b) && !a.returns() => *a,
+        expr => expr,
+    }
+}
+
+/// `Then(a, b)` becomes `b` if evaluating `a` has no effects.
+pub fn remove_pure_thens(expr: Expr) -> Expr {
+    match expr {
+        Expr::Then(a, b) if !has_effects(&a) => *b,
+        expr => expr,
+    }
+}
+
+/// `Let`s whose local is never read are removed, keeping the right hand side if it has effects. Assignments to the
+/// local are kept for their right hand sides' effects too.
+pub fn remove_unused_lets(expr: Expr) -> Expr {
+    match expr {
+        Expr::Let(rhs, then) if !reads(&then, 0) => {
+            let then = unbind(*then, 0);
+            if has_effects(&rhs) {
+                Expr::Then(rhs, Box::new(then))
+            } else {
+                then
+            }
+        }
+        expr => expr,
+    }
+}
+
+/// Loops whose assignments are all affine in their trip count are replaced with closed-form arithmetic. Only run when
+/// `Options::closed_form_loops` is set.
+pub fn close_loops(expr: Expr) -> Expr {
+    match InductionLoop::find(&expr) {
+        Some(lp) => closed_form(&lp),
+        None => expr,
+    }
+}
+
+// An expression that does nothing, and returns nothing
+fn nothing() -> Expr {
+    Expr::While(Box::new(Expr::Litr(0)), Box::new(Expr::Litr(0)))
+}
+
+fn is_dead_loop_pred(pred: &Expr) -> bool {
+    matches!(pred, Expr::Litr(x) if *x <= 0)
+}
+
+fn is_dead_loop(expr: &Expr) -> bool {
+    matches!(expr, Expr::While(pred, _) if is_dead_loop_pred(pred))
+}
+
+// Whether evaluating an expression may do anything other than produce a value. Loops count, since they might not
+// terminate, unless they're dead.
+fn has_effects(expr: &Expr) -> bool {
+    match expr {
+        Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => false,
+        Expr::Set(_, _) => true,
+        Expr::While(_, _) => !is_dead_loop(expr),
+        Expr::Add(x, y) | Expr::Let(x, y) | Expr::Then(x, y) => has_effects(x) || has_effects(y),
+    }
+}
+
+// Whether an expression reads the given local
+fn reads(expr: &Expr, local: LocalOffset) -> bool {
+    match expr {
+        Expr::Litr(_) | Expr::Arg(_) => false,
+        Expr::Get(l) => *l == local,
+        Expr::Set(_, rhs) => reads(rhs, local),
+        Expr::Let(rhs, then) => reads(rhs, local) || reads(then, local + 1),
+        Expr::Add(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+            reads(x, local) || reads(y, local)
+        }
+    }
+}
+
+// Remove a local that's never read from the scope of an expression, renumbering the locals declared outside of it and
+// replacing assignments to it with their right hand sides' effects
+fn unbind(expr: Expr, local: LocalOffset) -> Expr {
+    let go = |x: Box<Expr>, local| Box::new(unbind(*x, local));
+    match expr {
+        Expr::Litr(_) | Expr::Arg(_) => expr,
+        Expr::Get(l) => {
+            debug_assert_ne!(l, local);
+            Expr::Get(if l > local { l - 1 } else { l })
+        }
+        Expr::Set(l, rhs) if l == local => {
+            let rhs = unbind(*rhs, local);
+            if has_effects(&rhs) {
+                Expr::Then(Box::new(rhs), Box::new(nothing()))
+            } else {
+                nothing()
+            }
+        }
+        Expr::Set(l, rhs) => Expr::Set(if l > local { l - 1 } else { l }, go(rhs, local)),
+        Expr::Let(rhs, then) => Expr::Let(go(rhs, local), go(then, local + 1)),
+        Expr::Add(x, y) => Expr::Add(go(x, local), go(y, local)),
+        Expr::While(pred, body) => Expr::While(go(pred, local), go(body, local)),
+        Expr::Then(a, b) => Expr::Then(go(a, local), go(b, local)),
+    }
+}
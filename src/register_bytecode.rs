@@ -0,0 +1,191 @@
+use super::*;
+
+pub struct RegisterBytecode;
+
+type Reg = usize;
+
+#[derive(Debug)]
+pub enum Op {
+    Litr { dst: Reg, x: i64 },
+    Arg { dst: Reg, idx: usize },
+    Mov { dst: Reg, src: Reg },
+    Add { dst: Reg, a: Reg, b: Reg },
+    AddImm { dst: Reg, a: Reg, x: i64 },
+    JmpLeZ { reg: Reg, target: usize },
+    Jmp(usize),
+    Ret(Reg),
+}
+
+// Hands out virtual registers. Every local gets its own register for the duration of its `Let`, and temporaries are
+// allocated above the locals in scope, stack-style.
+struct Alloc {
+    locals: Vec<Reg>,
+    next: Reg,
+    max: Reg,
+}
+
+impl Alloc {
+    fn temp(&mut self) -> Reg {
+        let reg = self.next;
+        self.next += 1;
+        self.max = self.max.max(self.next);
+        reg
+    }
+
+    fn local(&self, local: LocalOffset) -> Reg {
+        self.locals[self.locals.len() - local - 1]
+    }
+}
+
+impl Vm for RegisterBytecode {
+    type Program<'a> = Frame<Vec<Op>>;
+    type Context = Vec<i64>; // registers
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        // A stand-in for expressions that don't return anything
+        const UNIT: i64 = 0;
+
+        fn has_set(expr: &Expr) -> bool {
+            match expr {
+                Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => false,
+                Expr::Set(_, _) => true,
+                Expr::Add(x, y) | Expr::Let(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+                    has_set(x) || has_set(y)
+                }
+            }
+        }
+
+        // Find a register holding the value of `expr`, only emitting code if it's not already a local
+        fn operand(ops: &mut Vec<Op>, alloc: &mut Alloc, expr: &Expr) -> Reg {
+            match expr {
+                Expr::Get(local) => alloc.local(*local),
+                _ => {
+                    let dst = alloc.temp();
+                    compile_inner(ops, alloc, expr, Some(dst));
+                    dst
+                }
+            }
+        }
+
+        // Compile `expr` so that its result ends up in `dst`, if the result is needed. `dst` is only ever written by the
+        // last instruction of an expression, so it's fine for `dst` to be a local that `expr` reads.
+        fn compile_inner(ops: &mut Vec<Op>, alloc: &mut Alloc, expr: &Expr, dst: Option<Reg>) {
+            let scope = alloc.next;
+            match expr {
+                Expr::Litr(x) => {
+                    let dst = dst.unwrap_or_else(|| alloc.temp());
+                    ops.push(Op::Litr { dst, x: *x });
+                }
+                Expr::Arg(idx) => {
+                    let dst = dst.unwrap_or_else(|| alloc.temp());
+                    ops.push(Op::Arg { dst, idx: *idx });
+                }
+                Expr::Get(local) => {
+                    if let Some(dst) = dst {
+                        ops.push(Op::Mov {
+                            dst,
+                            src: alloc.local(*local),
+                        });
+                    }
+                }
+                Expr::Add(x, y) => {
+                    // If `y` might change a local that `x` reads, take a copy of `x` first
+                    let a = if has_set(y) {
+                        let a = alloc.temp();
+                        compile_inner(ops, alloc, x, Some(a));
+                        a
+                    } else {
+                        operand(ops, alloc, x)
+                    };
+                    let dst = dst.unwrap_or_else(|| alloc.temp());
+                    match &**y {
+                        Expr::Litr(x) => ops.push(Op::AddImm { dst, a, x: *x }),
+                        _ => {
+                            let b = operand(ops, alloc, y);
+                            ops.push(Op::Add { dst, a, b });
+                        }
+                    }
+                }
+                Expr::Let(rhs, then) => {
+                    let local = alloc.temp();
+                    compile_inner(ops, alloc, rhs, Some(local));
+                    alloc.locals.push(local);
+                    compile_inner(ops, alloc, then, dst);
+                    alloc.locals.pop();
+                }
+                Expr::Set(local, rhs) => {
+                    let local = alloc.local(*local);
+                    compile_inner(ops, alloc, rhs, Some(local));
+                    if let Some(dst) = dst {
+                        ops.push(Op::Litr { dst, x: UNIT });
+                    }
+                }
+                Expr::While(pred, body) => {
+                    let start = ops.len();
+                    let reg = operand(ops, alloc, pred);
+                    let branch_fixup = ops.len();
+                    ops.push(Op::JmpLeZ { reg, target: 0 }); // Will be fixed up
+                    compile_inner(ops, alloc, body, None);
+                    ops.push(Op::Jmp(start));
+                    let end = ops.len();
+                    ops[branch_fixup] = Op::JmpLeZ { reg, target: end };
+                    if let Some(dst) = dst {
+                        ops.push(Op::Litr { dst, x: UNIT });
+                    }
+                }
+                Expr::Then(a, b) => {
+                    compile_inner(ops, alloc, a, None);
+                    compile_inner(ops, alloc, b, dst);
+                }
+            }
+            // Free any temporaries used by this expression
+            alloc.next = scope;
+        }
+
+        let mut ops = Vec::new();
+        let mut alloc = Alloc {
+            locals: Vec::new(),
+            next: 0,
+            max: 0,
+        };
+
+        let ret = alloc.temp();
+        compile_inner(&mut ops, &mut alloc, expr, Some(ret));
+
+        ops.push(Op::Ret(ret));
+
+        Frame {
+            code: ops,
+            max_locals: alloc.max, // Locals and temporaries share the register file
+            max_stack: 0,
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        Vec::new()
+    }
+
+    unsafe fn execute_in(regs: &mut Self::Context, prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+        reserve_slots(regs, prog.max_locals);
+        let regs = regs.as_mut_ptr();
+        let mut ip = 0;
+        loop {
+            let op = prog.code.get_unchecked(ip);
+            ip += 1;
+            match op {
+                Op::Litr { dst, x } => *regs.add(*dst) = *x,
+                Op::Arg { dst, idx } => *regs.add(*dst) = *args.get_unchecked(*idx),
+                Op::Mov { dst, src } => *regs.add(*dst) = *regs.add(*src),
+                Op::Add { dst, a, b } => *regs.add(*dst) = *regs.add(*a) + *regs.add(*b),
+                Op::AddImm { dst, a, x } => *regs.add(*dst) = *regs.add(*a) + *x,
+                Op::JmpLeZ { reg, target } => {
+                    if *regs.add(*reg) <= 0 {
+                        ip = *target;
+                    }
+                }
+                Op::Jmp(target) => ip = *target,
+                Op::Ret(reg) => break *regs.add(*reg),
+            }
+        }
+    }
+}
@@ -1,20 +1,116 @@
 use super::*;
 
-pub struct RegisterClosures;
+// Like `closures`, except `N` of the locals are passed through the closures in an array of "registers" rather than
+// living in memory. Every local's slot (its depth from the start of the frame) is known when compiling, so registers are
+// allocated to slots up front: the slots that are used most, counting each use inside a loop as `LOOP_WEIGHT` uses
+// outside of it, get a register for the whole execution, and every other slot keeps a fixed place in `locals`. No local
+// is live outside of the `Let` that binds it, so binding a new one never needs to save or restore the old value.
+pub struct RegisterClosures<const N: usize>;
 
-const REG_COUNT: usize = 2;
+// How many times more often code inside a loop is assumed to run than the code around it
+const LOOP_WEIGHT: u64 = 10;
 
-impl Vm for RegisterClosures {
-    type Program<'a> = Box<
-        dyn Fn(
-                *const i64,
-                *mut i64,
-                &mut [i64; REG_COUNT], // r1
-            ) -> i64
-            + 'a,
-    >;
+type Func<'a, const N: usize> = Box<
+    dyn Fn(
+            *const i64,
+            *mut i64,
+            &mut [i64; N], // registers
+        ) -> i64
+        + Send
+        + Sync
+        + 'a,
+>;
+
+impl<const N: usize> Vm for RegisterClosures<N> {
+    type Program<'a> = Frame<Func<'static, N>>;
+    type Context = Vec<i64>; // locals
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
+        Frame {
+            code: Self::compile_inner(expr, 0, &Self::registers(expr)),
+            max_locals: expr.max_locals(),
+            max_stack: 0, // Intermediate values live on the hardware stack
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        Vec::new()
+    }
+
+    unsafe fn execute_in(
+        locals: &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        reserve_slots(locals, prog.max_locals);
+        (prog.code)(args.as_ptr(), locals.as_mut_ptr(), &mut [0; N])
+    }
+}
+
+// Build a closure with the register index `$reg` as a constant, so that it isn't loaded from the closure's environment
+// every time the closure runs. Registers past the last arm are indexed at runtime instead.
+macro_rules! with_reg {
+    ($reg:expr, $r:ident => $make:expr) => {
+        with_reg!($reg, $r => $make; 0, 1, 2, 3, 4, 5, 6, 7)
+    };
+    ($reg:expr, $r:ident => $make:expr; $($n:literal),*) => {
+        match $reg {
+            $($n => {
+                const $r: usize = $n;
+                $make
+            })*
+            #[allow(non_snake_case)]
+            $r => $make,
+        }
+    };
+}
+
+impl<const N: usize> RegisterClosures<N> {
+    /// The register allocated to each local slot of a program, if any, indexed by the slot's depth from the start of
+    /// the frame.
+    pub fn registers(expr: &Expr) -> Vec<Option<usize>> {
+        let mut weights = vec![0; expr.max_locals()];
+        Self::weigh(expr, 0, 1, &mut weights);
+
+        // Ties go to the outermost slot, since it's live for longer
+        let mut slots = (0..weights.len()).collect::<Vec<_>>();
+        slots.sort_by_key(|&slot| std::cmp::Reverse(weights[slot]));
+        let mut registers = vec![None; weights.len()];
+        for (reg, slot) in slots.into_iter().take(N).enumerate() {
+            registers[slot] = Some(reg);
+        }
+        registers
+    }
+
+    // Add up how often each slot is bound, read and assigned, weighted by how deeply nested in loops each use is
+    fn weigh(expr: &Expr, depth: usize, weight: u64, weights: &mut [u64]) {
+        match expr {
+            Expr::Litr(_) | Expr::Arg(_) => {}
+            Expr::Get(local) => weights[depth - 1 - local] += weight,
+            Expr::Add(x, y) | Expr::Then(x, y) => {
+                Self::weigh(x, depth, weight, weights);
+                Self::weigh(y, depth, weight, weights);
+            }
+            Expr::Let(rhs, then) => {
+                Self::weigh(rhs, depth, weight, weights);
+                weights[depth] += weight;
+                Self::weigh(then, depth + 1, weight, weights);
+            }
+            Expr::Set(local, rhs) => {
+                weights[depth - 1 - local] += weight;
+                Self::weigh(rhs, depth, weight, weights);
+            }
+            Expr::While(pred, body) => {
+                let weight = weight.saturating_mul(LOOP_WEIGHT);
+                Self::weigh(pred, depth, weight, weights);
+                Self::weigh(body, depth, weight, weights);
+            }
+        }
+    }
+
+    // `depth` is the number of locals in scope, and `registers` comes from `Self::registers`. Register indices are
+    // always below `N`, so they're used unchecked.
+    fn compile_inner(expr: &Expr, depth: usize, registers: &[Option<usize>]) -> Func<'static, N> {
         // A stand-in for expressions that don't return anything
         const UNIT: i64 = 0;
 
@@ -27,78 +123,79 @@ impl Vm for RegisterClosures {
                 let idx = *idx;
                 Box::new(move |args, _, _| unsafe { *args.add(idx) })
             }
-            Expr::Get(local) => match local {
-                0 => Box::new(move |_, _, r| r[0]),
-                1 => Box::new(move |_, _, r| r[1]),
-                _ => {
-                    let offset = -1 - *local as isize + REG_COUNT as isize;
-                    Box::new(move |_, locals, _| unsafe { *locals.offset(offset) })
+            Expr::Get(local) => {
+                let slot = depth - 1 - local;
+                match registers[slot] {
+                    Some(reg) => {
+                        with_reg!(reg, REG => Box::new(move |_, _, r| unsafe { *r.get_unchecked(REG) }))
+                    }
+                    None => Box::new(move |_, locals, _| unsafe { *locals.add(slot) }),
                 }
-            },
+            }
             Expr::Add(x, y) => match &**y {
                 Expr::Litr(1) => {
-                    let x = Self::compile(x);
+                    let x = Self::compile_inner(x, depth, registers);
                     Box::new(move |args, locals, r| x(args, locals, r) + 1)
                 }
                 Expr::Litr(y) => {
-                    let x = Self::compile(x);
+                    let x = Self::compile_inner(x, depth, registers);
                     let y = *y;
                     Box::new(move |args, locals, r| x(args, locals, r) + y)
                 }
                 Expr::Arg(1) => {
-                    let x = Self::compile(x);
+                    let x = Self::compile_inner(x, depth, registers);
                     Box::new(move |args, locals, r| x(args, locals, r) + unsafe { *args.add(1) })
                 }
                 _ => {
-                    let x = Self::compile(x);
-                    let y = Self::compile(y);
+                    let x = Self::compile_inner(x, depth, registers);
+                    let y = Self::compile_inner(y, depth, registers);
                     Box::new(move |args, locals, r| x(args, locals, r) + y(args, locals, r))
                 }
             },
             Expr::Let(rhs, then) => {
-                let rhs = Self::compile(rhs);
-                let then = Self::compile(then);
-                Box::new(move |args, locals, r| {
-                    let rhs = rhs(args, locals, r);
-                    unsafe {
-                        locals.write(r[1]);
-                    }
-                    r[1] = r[0];
-                    r[0] = rhs;
-                    let res = then(args, unsafe { locals.add(1) }, r);
-                    r[0] = r[1];
-                    unsafe {
-                        r[1] = locals.read();
-                    }
-                    res
-                })
+                let slot = depth;
+                let rhs = Self::compile_inner(rhs, depth, registers);
+                let then = Self::compile_inner(then, depth + 1, registers);
+                match registers[slot] {
+                    Some(reg) => with_reg!(reg, REG => Box::new(move |args, locals, r| {
+                        let rhs = rhs(args, locals, r);
+                        unsafe {
+                            *r.get_unchecked_mut(REG) = rhs;
+                        }
+                        then(args, locals, r)
+                    })),
+                    None => Box::new(move |args, locals, r| {
+                        let rhs = rhs(args, locals, r);
+                        unsafe {
+                            *locals.add(slot) = rhs;
+                        }
+                        then(args, locals, r)
+                    }),
+                }
             }
             Expr::Set(local, rhs) => {
-                let rhs = Self::compile(rhs);
-                match local {
-                    0 => Box::new(move |args, locals, r| {
-                        r[0] = rhs(args, locals, r);
+                let slot = depth - 1 - local;
+                let rhs = Self::compile_inner(rhs, depth, registers);
+                match registers[slot] {
+                    Some(reg) => with_reg!(reg, REG => Box::new(move |args, locals, r| {
+                        let rhs = rhs(args, locals, r);
+                        unsafe {
+                            *r.get_unchecked_mut(REG) = rhs;
+                        }
                         UNIT
-                    }),
-                    1 => Box::new(move |args, locals, r| {
-                        r[1] = rhs(args, locals, r);
+                    })),
+                    None => Box::new(move |args, locals, r| {
+                        let rhs = rhs(args, locals, r);
+                        unsafe {
+                            *locals.add(slot) = rhs;
+                        }
                         UNIT
                     }),
-                    _ => {
-                        let offset = -1 - *local as isize + REG_COUNT as isize;
-                        Box::new(move |args, locals, r| {
-                            let rhs = rhs(args, locals, r);
-                            unsafe {
-                                *locals.offset(offset) = rhs;
-                            }
-                            UNIT
-                        })
-                    }
                 }
             }
             Expr::While(pred, body) => {
-                let pred = Self::compile(pred);
-                let body = Self::compile(body);
+                let pred = Self::compile_inner(pred, depth, registers);
+                let body = Self::compile_inner(body, depth, registers);
                 Box::new(move |args, locals, r| {
                     while pred(args, locals, r) > 0 {
                         body(args, locals, r);
@@ -107,8 +204,8 @@ impl Vm for RegisterClosures {
                 })
             }
             Expr::Then(a, b) => {
-                let a = Self::compile(a);
-                let b = Self::compile(b);
+                let a = Self::compile_inner(a, depth, registers);
+                let b = Self::compile_inner(b, depth, registers);
                 Box::new(move |args, locals, r| {
                     a(args, locals, r);
                     b(args, locals, r)
@@ -116,9 +213,4 @@ impl Vm for RegisterClosures {
             }
         }
     }
-
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
-        let mut v = vec![0; 64];
-        prog(args.as_ptr(), v.as_mut_ptr(), &mut [0; REG_COUNT])
-    }
 }
@@ -0,0 +1,122 @@
+use super::*;
+
+// Peephole specialisations, shared by the backends that have them. Each rule names a shape of expression that a backend
+// can compile to something cheaper than the general case, such as `x + 1` becoming an increment. Backends ask for the
+// rule matching a node with `Rewrite::find`, and match on the result exhaustively, so every one of them handles every rule.
+//
+// Rules are written as `Name { fields } = Pattern if guard;`, where patterns mirror `Expr`:
+// - `Litr(..)`, `Arg(..)`, `Get(..)` and the local of `Set(..)` take a literal that must match, a name to bind, or `_`
+// - Everything else takes sub-patterns, and a name in place of one binds the subexpression
+// Rules are tried in order, so more specific ones go first.
+
+// Check a number in a pattern, binding it if it's a name
+macro_rules! scalar {
+    ($v:expr => _) => {};
+    ($v:expr => $lit:literal) => {
+        if $v != $lit {
+            return None;
+        }
+    };
+    ($v:expr => $x:ident) => {
+        let $x = $v;
+    };
+}
+
+// Match an `&Expr` against a pattern, binding its names, or return `None`
+macro_rules! pattern {
+    ($e:expr => _) => {};
+    ($e:expr => Litr($($x:tt)+)) => {
+        let Expr::Litr(v) = $e else { return None };
+        scalar!(*v => $($x)+);
+    };
+    ($e:expr => Arg($($x:tt)+)) => {
+        let Expr::Arg(v) = $e else { return None };
+        scalar!(*v => $($x)+);
+    };
+    ($e:expr => Get($($x:tt)+)) => {
+        let Expr::Get(v) = $e else { return None };
+        scalar!(*v => $($x)+);
+    };
+    ($e:expr => Set($x:tt, $y:tt $(($($ya:tt)*))?)) => {
+        let Expr::Set(v, y) = $e else { return None };
+        scalar!(*v => $x);
+        pattern!(&**y => $y $(($($ya)*))?);
+    };
+    ($e:expr => $variant:ident($x:tt $(($($xa:tt)*))?, $y:tt $(($($ya:tt)*))?)) => {
+        let Expr::$variant(x, y) = $e else { return None };
+        pattern!(&**x => $x $(($($xa)*))?);
+        pattern!(&**y => $y $(($($ya)*))?);
+    };
+    ($e:expr => $x:ident) => {
+        let $x: &Expr = $e;
+    };
+}
+
+macro_rules! rewrites {
+    ($(
+        $(#[$attr:meta])*
+        $name:ident { $($field:ident: $ty:ty),* $(,)? } = $variant:ident($($args:tt)*) $(if $guard:expr)?;
+    )*) => {
+        /// A node that matched one of the rules, along with the parts of it that the specialisation needs.
+        #[derive(Clone, Copy)]
+        pub enum Rewrite<'a> {
+            $($(#[$attr])* $name { $($field: $ty),* },)*
+        }
+
+        impl<'a> Rewrite<'a> {
+            /// The name of every rule, in the order they're tried.
+            pub const RULES: &'static [&'static str] = &[$(stringify!($name)),*];
+
+            /// Find the first rule that matches the root of an expression.
+            pub fn find(expr: &'a Expr) -> Option<Self> {
+                $(
+                    #[allow(non_snake_case)]
+                    fn $name(expr: &Expr) -> Option<Rewrite<'_>> {
+                        pattern!(expr => $variant($($args)*));
+                        $(if !$guard {
+                            return None;
+                        })?
+                        Some(Rewrite::$name { $($field),* })
+                    }
+                    if let Some(rewrite) = $name(expr) {
+                        return Some(rewrite);
+                    }
+                )*
+                None
+            }
+
+            /// The name of the rule that matched.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Rewrite::$name { .. } => stringify!($name),)*
+                }
+            }
+        }
+    };
+}
+
+rewrites! {
+    /// `x + 1`
+    AddOne { x: &'a Expr } = Add(x, Litr(1));
+    /// `x + -1`
+    SubOne { x: &'a Expr } = Add(x, Litr(-1));
+    /// `x + y`, for a literal `y`
+    AddLitr { x: &'a Expr, y: i64 } = Add(x, Litr(y));
+    /// `x + args[1]`
+    AddArg1 { x: &'a Expr } = Add(x, Arg(1));
+    /// `#local = #local + y`, which can update the local in place. `y` is evaluated after the local is read, so it
+    /// mustn't assign to any locals.
+    AddAssign { local: LocalOffset, y: &'a Expr } = Set(local, Add(Get(get), y))
+        if local == get && !assigns(y);
+}
+
+// Whether evaluating an expression may assign to a local that's live outside of it
+fn assigns(expr: &Expr) -> bool {
+    match expr {
+        Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => false,
+        Expr::Set(_, _) => true,
+        Expr::Add(x, y) | Expr::Let(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+            assigns(x) || assigns(y)
+        }
+    }
+}
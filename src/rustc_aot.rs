@@ -0,0 +1,282 @@
+use super::*;
+use core::ffi::{c_char, c_int, c_void, CStr};
+use std::{
+    env::consts::DLL_SUFFIX,
+    ffi::CString,
+    fmt::Write,
+    fs,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Ahead-of-time compilation: the program is lowered to Rust source, built into a `cdylib` by the same rustc that built
+// this crate, and loaded with `dlopen`. This gives a "compiled" reference point for any program, rather than just the
+// hand-written `rust_impl`s in the benchmarks.
+//
+// rustc takes a fraction of a second even for tiny programs, so libraries are cached in the build directory, keyed by a
+// hash of their source and of how they were built. Only the first compilation of a given program pays for rustc.
+pub struct RustcAot;
+
+pub(crate) type AotFn = unsafe extern "C" fn(*const i64) -> i64;
+
+// The rustc that built this crate, passed along by `build.rs`
+const RUSTC: Compiler = Compiler {
+    program: env!("VM_PERF_RUSTC"),
+    flags: &[
+        "--crate-type=cdylib",
+        "--crate-name=program",
+        "--edition=2021",
+        "-C",
+        "opt-level=3",
+        "-C",
+        "panic=abort",
+    ],
+    extension: "rs",
+};
+
+// The symbol exported by every generated library
+pub(crate) const ENTRY: &CStr = c"run";
+
+// A loaded library, along with the program's entry point in it
+pub struct Library {
+    handle: *mut c_void,
+    f: AotFn,
+}
+
+// SAFETY: The library is never modified once it's loaded, and `dlsym`/`dlclose` are thread-safe
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+const RTLD_NOW: c_int = 0x2;
+
+#[cfg_attr(target_os = "linux", link(name = "dl"))]
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+    fn dlerror() -> *const c_char;
+}
+
+extern "C" {
+    fn geteuid() -> u32;
+}
+
+fn dl_error() -> String {
+    unsafe { CStr::from_ptr(dlerror()).to_string_lossy().into_owned() }
+}
+
+impl Library {
+    fn open(path: &Path) -> Self {
+        let filename = CString::new(path.as_os_str().as_encoded_bytes()).unwrap();
+        unsafe {
+            let handle = dlopen(filename.as_ptr(), RTLD_NOW);
+            assert!(
+                !handle.is_null(),
+                "failed to load {}: {}",
+                path.display(),
+                dl_error()
+            );
+            let f = dlsym(handle, ENTRY.as_ptr());
+            assert!(!f.is_null(), "failed to find entry point: {}", dl_error());
+            Self {
+                handle,
+                f: core::mem::transmute::<*mut c_void, AotFn>(f),
+            }
+        }
+    }
+
+    pub(crate) fn entry(&self) -> AotFn {
+        self.f
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            dlclose(self.handle);
+        }
+    }
+}
+
+// 64-bit FNV-1a, which (unlike `DefaultHasher`) is stable between builds, so the cache stays valid
+fn content_hash(parts: &[&str]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for part in parts {
+        // Terminate each part, so that moving bytes between them changes the hash
+        for byte in part.bytes().chain([0]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+// The cache lives in this crate's build directory, and only its owner can write to it. Somewhere shared, like the system
+// temporary directory, would let another user plant a library under the name of one that's about to be loaded.
+fn cache_dir() -> PathBuf {
+    let dir = Path::new(env!("OUT_DIR")).join("aot");
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .expect("failed to create cache directory");
+    dir
+}
+
+// A compiler that builds a shared library from a single source file, exporting `ENTRY`
+pub(crate) struct Compiler {
+    pub(crate) program: &'static str,
+    pub(crate) flags: &'static [&'static str],
+    // Of source files
+    pub(crate) extension: &'static str,
+}
+
+impl Compiler {
+    // Build a library from source, unless it's already in the cache, and load it. The source is kept alongside the
+    // library, for inspection.
+    pub(crate) fn load(&self, source: &str) -> Library {
+        let dir = cache_dir();
+        let hash = content_hash(&[self.program, &self.flags.join(" "), source]);
+        let path = dir.join(format!("{hash:016x}{DLL_SUFFIX}"));
+        if !path.exists() {
+            self.build(&dir, hash, source, &path);
+        }
+        // `dlopen` runs the library's initialisers, so don't trust anything this user didn't build
+        let meta = fs::symlink_metadata(&path).expect("failed to inspect library");
+        assert!(
+            meta.is_file() && meta.uid() == unsafe { geteuid() },
+            "refusing to load {}, which wasn't built by this user",
+            path.display()
+        );
+        Library::open(&path)
+    }
+
+    fn build(&self, dir: &Path, hash: u64, source: &str, path: &Path) {
+        // Several threads or processes may build the same program at once, so each builds under its own name and then
+        // moves the result into place, which is atomic
+        static BUILDS: AtomicUsize = AtomicUsize::new(0);
+        let tmp = format!(
+            "{hash:016x}.{}.{}",
+            std::process::id(),
+            BUILDS.fetch_add(1, Ordering::Relaxed)
+        );
+        let src_path = dir.join(format!("{tmp}.{}", self.extension));
+        let lib_path = dir.join(format!("{tmp}{DLL_SUFFIX}"));
+
+        fs::write(&src_path, source).expect("failed to write source");
+        let output = Command::new(self.program)
+            .args(self.flags)
+            .arg("-o")
+            .arg(&lib_path)
+            .arg(&src_path)
+            .output()
+            .unwrap_or_else(|err| panic!("failed to run {}: {err}", self.program));
+        assert!(
+            output.status.success(),
+            "failed to compile program:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let _ = fs::rename(
+            &src_path,
+            dir.join(format!("{hash:016x}.{}", self.extension)),
+        );
+        fs::rename(&lib_path, path).expect("failed to move library into the cache");
+    }
+}
+
+// Lowers an expression to the body of a Rust function, as a flat list of statements. Deep expressions would be deeply
+// nested Rust expressions otherwise, which rustc can overflow its own stack on.
+struct Lowering {
+    out: String,
+    temps: usize,
+}
+
+impl Lowering {
+    // Emit the statements for an expression, and return a Rust operand for its value. Every local becomes a Rust
+    // variable, named by its depth from the bottom of the locals, and `args` is the pointer passed to the entry point.
+    fn lower(&mut self, expr: &Expr, depth: usize) -> String {
+        // A stand-in for expressions that don't return anything
+        const UNIT: &str = "0";
+
+        match expr {
+            Expr::Litr(x) => format!("({x}i64)"),
+            Expr::Arg(idx) => format!("(*args.add({idx}))"),
+            // Read into a temporary, since the local may be set before the value is used
+            Expr::Get(local) => self.temp(format_args!("l{}", depth - local - 1)),
+            Expr::Add(x, y) => {
+                let x = self.lower(x, depth);
+                let y = self.lower(y, depth);
+                self.temp(format_args!("{x} + {y}"))
+            }
+            Expr::Let(rhs, then) => {
+                let rhs = self.lower(rhs, depth);
+                writeln!(self.out, "let mut l{depth} = {rhs};").unwrap();
+                self.lower(then, depth + 1)
+            }
+            Expr::Set(local, rhs) => {
+                let rhs = self.lower(rhs, depth);
+                writeln!(self.out, "l{} = {rhs};", depth - local - 1).unwrap();
+                UNIT.to_string()
+            }
+            Expr::While(pred, body) => {
+                self.out.push_str("loop {\n");
+                let pred = self.lower(pred, depth);
+                writeln!(self.out, "if {pred} <= 0 {{ break; }}").unwrap();
+                self.lower(body, depth);
+                self.out.push_str("}\n");
+                UNIT.to_string()
+            }
+            Expr::Then(a, b) => {
+                self.lower(a, depth);
+                self.lower(b, depth)
+            }
+        }
+    }
+
+    fn temp(&mut self, value: core::fmt::Arguments) -> String {
+        let temp = format!("t{}", self.temps);
+        self.temps += 1;
+        writeln!(self.out, "let {temp} = {value};").unwrap();
+        temp
+    }
+}
+
+impl RustcAot {
+    /// The Rust source that a program compiles to.
+    pub fn source(expr: &Expr) -> String {
+        let mut lowering = Lowering {
+            out: String::new(),
+            temps: 0,
+        };
+        let result = lowering.lower(expr, 0);
+        // Generated code is full of needless `mut`s and shadowed variables
+        format!(
+            "#![allow(warnings)]\n\
+             #[no_mangle]\n\
+             pub unsafe extern \"C\" fn {}(args: *const i64) -> i64 {{\n{}{result}\n}}\n",
+            ENTRY.to_str().unwrap(),
+            lowering.out,
+        )
+    }
+}
+
+impl Vm for RustcAot {
+    type Program<'a> = Frame<Library>;
+    type Context = ();
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        Frame {
+            code: RUSTC.load(&Self::source(expr)),
+            max_locals: expr.max_locals(),
+            max_stack: 0, // Intermediate values are up to rustc
+        }
+    }
+
+    fn new_context() -> Self::Context {}
+
+    unsafe fn execute_in(_: &mut Self::Context, prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+        (prog.code.entry())(args.as_ptr())
+    }
+}
@@ -0,0 +1,170 @@
+use super::*;
+use std::simd::{cmp::SimdPartialOrd, Mask, Select, Simd};
+
+// Executes N argument sets in lockstep, one per SIMD lane. Each closure dispatch is shared by every lane, so the cost of
+// dispatch is amortised over the lanes. Lanes whose loops have finished are masked off until every lane has finished.
+pub struct SimdClosures<const N: usize>;
+
+pub type Lanes<const N: usize> = Simd<i64, N>;
+
+type Func<const N: usize> = Box<
+    dyn Fn(
+            *const Lanes<N>, // args
+            *mut Lanes<N>,   // locals
+            Mask<i64, N>,    // lanes that are still running
+        ) -> Lanes<N>
+        + Send
+        + Sync,
+>;
+
+impl<const N: usize> Vm for SimdClosures<N> {
+    type Program<'a> = Frame<Func<N>>;
+    type Context = (Vec<Lanes<N>>, Vec<Lanes<N>>); // (args, locals)
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        Frame {
+            code: Self::compile_inner(expr),
+            max_locals: expr.max_locals(),
+            max_stack: 0, // Intermediate values live on the hardware stack
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
+    }
+
+    // Runs the program with the same arguments in every lane, which is only useful for checking against other backends
+    unsafe fn execute_in(
+        (lane_args, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        lane_args.clear();
+        lane_args.extend(args.iter().map(|arg| Lanes::splat(*arg)));
+        Self::execute_lanes_in(locals, prog, lane_args, Mask::splat(true))[0]
+    }
+
+    unsafe fn execute_batch_in(
+        (lane_args, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[&[i64]],
+        out: &mut [i64],
+    ) {
+        assert_eq!(args.len(), out.len());
+        for (args, out) in args.chunks(N).zip(out.chunks_mut(N)) {
+            // Transpose the argument sets into lanes, leaving any lanes past the end of the batch disabled
+            let arity = args.iter().map(|args| args.len()).max().unwrap_or(0);
+            lane_args.clear();
+            lane_args.extend((0..arity).map(|i| {
+                Lanes::from_array(core::array::from_fn(|lane| {
+                    args.get(lane)
+                        .and_then(|args| args.get(i))
+                        .copied()
+                        .unwrap_or(0)
+                }))
+            }));
+            let active = Mask::from_array(core::array::from_fn(|lane| lane < args.len()));
+            let res = Self::execute_lanes_in(locals, prog, lane_args, active);
+            out.copy_from_slice(&res[..out.len()]);
+        }
+    }
+}
+
+impl<const N: usize> SimdClosures<N> {
+    /// Execute the program over N argument sets at once, where `args[i]` holds the ith argument of every set.
+    ///
+    /// # Safety
+    ///
+    /// `prog` must have been produced by `Self::compile`, and `args` must hold a value for every argument it reads.
+    pub unsafe fn execute_lanes(prog: &Frame<Func<N>>, args: &[Lanes<N>]) -> Lanes<N> {
+        Self::execute_lanes_in(&mut Vec::new(), prog, args, Mask::splat(true))
+    }
+
+    unsafe fn execute_lanes_in(
+        locals: &mut Vec<Lanes<N>>,
+        prog: &Frame<Func<N>>,
+        args: &[Lanes<N>],
+        active: Mask<i64, N>,
+    ) -> Lanes<N> {
+        reserve_slots(locals, prog.max_locals);
+        (prog.code)(args.as_ptr(), locals.as_mut_ptr(), active)
+    }
+
+    fn compile_inner(expr: &Expr) -> Func<N> {
+        // A stand-in for expressions that don't return anything
+        const UNIT: i64 = 0;
+
+        match expr {
+            Expr::Litr(x) => {
+                let x = Lanes::splat(*x);
+                Box::new(move |_, _, _| x)
+            }
+            Expr::Arg(idx) => {
+                let idx = *idx;
+                Box::new(move |args, _, _| unsafe { *args.add(idx) })
+            }
+            Expr::Get(local) => {
+                let offset = -1 - *local as isize;
+                Box::new(move |_, locals, _| unsafe { *locals.offset(offset) })
+            }
+            Expr::Add(x, y) => match &**y {
+                Expr::Litr(y) => {
+                    let x = Self::compile_inner(x);
+                    let y = Lanes::splat(*y);
+                    Box::new(move |args, locals, m| x(args, locals, m) + y)
+                }
+                _ => {
+                    let x = Self::compile_inner(x);
+                    let y = Self::compile_inner(y);
+                    Box::new(move |args, locals, m| x(args, locals, m) + y(args, locals, m))
+                }
+            },
+            Expr::Let(rhs, then) => {
+                let rhs = Self::compile_inner(rhs);
+                let then = Self::compile_inner(then);
+                Box::new(move |args, locals, m| {
+                    let rhs = rhs(args, locals, m);
+                    unsafe {
+                        locals.write(rhs);
+                    }
+                    then(args, unsafe { locals.add(1) }, m)
+                })
+            }
+            Expr::Set(local, rhs) => {
+                let rhs = Self::compile_inner(rhs);
+                let offset = -1 - *local as isize;
+                Box::new(move |args, locals, m| {
+                    let rhs = rhs(args, locals, m);
+                    unsafe {
+                        let local = locals.offset(offset);
+                        // Lanes that aren't running keep their old value
+                        local.write(m.select(rhs, local.read()));
+                    }
+                    Lanes::splat(UNIT)
+                })
+            }
+            Expr::While(pred, body) => {
+                let pred = Self::compile_inner(pred);
+                let body = Self::compile_inner(body);
+                Box::new(move |args, locals, mut m| {
+                    loop {
+                        m &= pred(args, locals, m).simd_gt(Lanes::splat(0));
+                        if !m.any() {
+                            break;
+                        }
+                        body(args, locals, m);
+                    }
+                    Lanes::splat(UNIT)
+                })
+            }
+            Expr::Then(a, b) => {
+                let a = Self::compile_inner(a);
+                let b = Self::compile_inner(b);
+                Box::new(move |args, locals, m| {
+                    a(args, locals, m);
+                    b(args, locals, m)
+                })
+            }
+        }
+    }
+}
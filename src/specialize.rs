@@ -0,0 +1,156 @@
+use super::*;
+
+// Partial evaluation: rewrite a program for arguments that are known ahead of time, like configuration that stays the
+// same over many calls. Known arguments become literals, and constants are propagated through locals and additions.
+// Loops whose trip count becomes known (and small) are unrolled, so the result only does the work that depends on the
+// unknown arguments.
+
+// Loops that would take more iterations than this are left as loops
+const MAX_UNROLL: usize = 16;
+
+/// Specialise a program on the arguments that are known ahead of time. The result takes the same arguments as the
+/// original, so it can be compiled by any backend, but only gives the same results for the known values.
+pub fn specialize(expr: &Expr, known_args: &[Option<i64>]) -> Expr {
+    Specializer { known_args }
+        .specialize(expr, &mut Vec::new())
+        .0
+}
+
+struct Specializer<'a> {
+    known_args: &'a [Option<i64>],
+}
+
+// An expression that does nothing, and returns nothing
+fn nothing() -> Expr {
+    Expr::While(Box::new(Expr::Litr(0)), Box::new(Expr::Litr(0)))
+}
+
+impl Specializer<'_> {
+    // Returns the rewritten expression, along with its value if that's known. `locals` holds the known value of each
+    // local, innermost last, and is updated by assignments.
+    fn specialize(&self, expr: &Expr, locals: &mut Vec<Option<i64>>) -> (Expr, Option<i64>) {
+        match expr {
+            Expr::Litr(x) => (Expr::Litr(*x), Some(*x)),
+            Expr::Arg(idx) => match self.known_args.get(*idx).copied().flatten() {
+                Some(x) => (Expr::Litr(x), Some(x)),
+                None => (Expr::Arg(*idx), None),
+            },
+            Expr::Get(local) => match locals[locals.len() - local - 1] {
+                Some(x) => (Expr::Litr(x), Some(x)),
+                None => (Expr::Get(*local), None),
+            },
+            Expr::Add(x, y) => {
+                let (x, x_val) = self.specialize(x, locals);
+                let (y, y_val) = self.specialize(y, locals);
+                match (&x, &y, x_val.zip(y_val)) {
+                    (Expr::Litr(_), Expr::Litr(_), Some((x, y))) => {
+                        (Expr::Litr(x.wrapping_add(y)), Some(x.wrapping_add(y)))
+                    }
+                    // Either side may have effects, so both are kept
+                    (_, _, sum) => (
+                        Expr::Add(Box::new(x), Box::new(y)),
+                        sum.map(|(x, y)| x.wrapping_add(y)),
+                    ),
+                }
+            }
+            Expr::Let(rhs, then) => {
+                let (rhs, rhs_val) = self.specialize(rhs, locals);
+                locals.push(rhs_val);
+                let (then, then_val) = self.specialize(then, locals);
+                locals.pop();
+                (Expr::Let(Box::new(rhs), Box::new(then)), then_val)
+            }
+            Expr::Set(local, rhs) => {
+                let (rhs, rhs_val) = self.specialize(rhs, locals);
+                let len = locals.len();
+                locals[len - local - 1] = rhs_val;
+                (Expr::Set(*local, Box::new(rhs)), None)
+            }
+            Expr::While(pred, body) => {
+                let before = locals.clone();
+                match self.unroll(pred, body, locals) {
+                    Some(unrolled) => (unrolled, None),
+                    None => {
+                        *locals = before;
+                        (self.specialize_loop(pred, body, locals), None)
+                    }
+                }
+            }
+            Expr::Then(a, b) => {
+                let (a, _) = self.specialize(a, locals);
+                let (b, b_val) = self.specialize(b, locals);
+                (Expr::Then(Box::new(a), Box::new(b)), b_val)
+            }
+        }
+    }
+
+    // Unroll a loop, if the predicate is known for every iteration and it finishes within `MAX_UNROLL` of them
+    fn unroll(&self, pred: &Expr, body: &Expr, locals: &mut Vec<Option<i64>>) -> Option<Expr> {
+        let mut iterations = Vec::new();
+        loop {
+            let (pred, pred_val) = self.specialize(pred, locals);
+            // The predicate is still evaluated, for its effects, unless it's just a literal
+            let pred = (!matches!(pred, Expr::Litr(_))).then_some(pred);
+            if pred_val? <= 0 {
+                iterations.extend(pred);
+                break;
+            }
+            if iterations.len() >= MAX_UNROLL {
+                return None;
+            }
+            let (body, _) = self.specialize(body, locals);
+            iterations.push(match pred {
+                Some(pred) => Expr::Then(Box::new(pred), Box::new(body)),
+                None => body,
+            });
+        }
+        Some(
+            iterations
+                .into_iter()
+                .rev()
+                .fold(nothing(), |rest, iteration| {
+                    Expr::Then(Box::new(iteration), Box::new(rest))
+                }),
+        )
+    }
+
+    // Specialise a loop that stays a loop. Any local it assigns could have any value, both inside it and afterwards.
+    fn specialize_loop(&self, pred: &Expr, body: &Expr, locals: &mut Vec<Option<i64>>) -> Expr {
+        let mut assigned = Vec::new();
+        assigned_locals(pred, locals.len(), &mut assigned);
+        assigned_locals(body, locals.len(), &mut assigned);
+        let forget = |locals: &mut Vec<Option<i64>>| {
+            for &idx in &assigned {
+                locals[idx] = None;
+            }
+        };
+
+        forget(locals);
+        let (pred, _) = self.specialize(pred, locals);
+        let (body, _) = self.specialize(body, locals);
+        forget(locals);
+        Expr::While(Box::new(pred), Box::new(body))
+    }
+}
+
+// Collect the locals that an expression assigns to, as indices from the bottom of the locals, leaving out the ones it
+// declares itself
+fn assigned_locals(expr: &Expr, len: usize, out: &mut Vec<usize>) {
+    match expr {
+        Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => {}
+        Expr::Add(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+            assigned_locals(x, len, out);
+            assigned_locals(y, len, out);
+        }
+        Expr::Let(rhs, then) => {
+            assigned_locals(rhs, len, out);
+            let mut inner = Vec::new();
+            assigned_locals(then, len + 1, &mut inner);
+            out.extend(inner.into_iter().filter(|&idx| idx < len));
+        }
+        Expr::Set(local, rhs) => {
+            out.push(len - local - 1);
+            assigned_locals(rhs, len, out);
+        }
+    }
+}
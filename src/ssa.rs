@@ -0,0 +1,683 @@
+use super::*;
+use crate::{bytecode::Op, closures::make_func};
+use core::{fmt, marker::PhantomData};
+
+// An intermediate representation in static single assignment form, between `Expr` and the backends. Locals disappear:
+// each assignment defines a new value instead, and where control flow merges (at the head of each loop), phi nodes pick
+// the value of each local according to where control came from. Analyses and optimisations can be written once against
+// the IR, and every backend that compiles from it benefits.
+//
+// `Ssa<V>` is a backend that compiles via the IR, for each `V` that implements `FromIr`.
+
+/// The index of a value, which is the instruction that defines it.
+pub type Value = usize;
+/// The index of a basic block.
+pub type BlockId = usize;
+
+/// A program in SSA form. Execution starts at the first block.
+pub struct Ir {
+    /// The instruction defining each value. Instructions can be left unused, by optimisations for example.
+    pub insts: Vec<Inst>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inst {
+    Litr(i64),
+    Arg(usize),
+    Add(Value, Value),
+    /// The value from each predecessor of the block. Phis always come first in their block.
+    Phi(Vec<(BlockId, Value)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    /// The values defined in this block, in order.
+    pub insts: Vec<Value>,
+    pub term: Term,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    Jmp(BlockId),
+    /// Go to the first block if the value is positive, otherwise to the second.
+    Branch(Value, BlockId, BlockId),
+    Ret(Value),
+}
+
+impl Inst {
+    /// The values used by the instruction.
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Inst::Litr(_) | Inst::Arg(_) => Vec::new(),
+            Inst::Add(x, y) => vec![*x, *y],
+            Inst::Phi(preds) => preds.iter().map(|(_, v)| *v).collect(),
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Inst::Litr(_) | Inst::Arg(_) => Vec::new(),
+            Inst::Add(x, y) => vec![x, y],
+            Inst::Phi(preds) => preds.iter_mut().map(|(_, v)| v).collect(),
+        }
+    }
+}
+
+impl Term {
+    /// The blocks that control can pass to.
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Term::Jmp(to) => vec![*to],
+            Term::Branch(_, then, els) => vec![*then, *els],
+            Term::Ret(_) => Vec::new(),
+        }
+    }
+
+    fn operand_mut(&mut self) -> Option<&mut Value> {
+        match self {
+            Term::Jmp(_) => None,
+            Term::Branch(v, _, _) | Term::Ret(v) => Some(v),
+        }
+    }
+}
+
+// Builds the IR from an `Expr`, tracking the current value of each local
+struct Builder {
+    insts: Vec<Inst>,
+    // The terminator of each block is filled in once control leaves it
+    blocks: Vec<(Vec<Value>, Option<Term>)>,
+    current: BlockId,
+}
+
+impl Builder {
+    fn emit(&mut self, inst: Inst) -> Value {
+        let value = self.insts.len();
+        self.insts.push(inst);
+        self.blocks[self.current].0.push(value);
+        value
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push((Vec::new(), None));
+        self.blocks.len() - 1
+    }
+
+    fn terminate(&mut self, term: Term) {
+        self.blocks[self.current].1 = Some(term);
+    }
+
+    // `locals` holds the current value of each local, innermost last
+    fn build(&mut self, expr: &Expr, locals: &mut Vec<Value>) -> Value {
+        // A stand-in for expressions that don't return anything
+        const UNIT: i64 = 0;
+
+        match expr {
+            Expr::Litr(x) => self.emit(Inst::Litr(*x)),
+            Expr::Arg(idx) => self.emit(Inst::Arg(*idx)),
+            Expr::Get(local) => locals[locals.len() - local - 1],
+            Expr::Add(x, y) => {
+                let x = self.build(x, locals);
+                let y = self.build(y, locals);
+                self.emit(Inst::Add(x, y))
+            }
+            Expr::Let(rhs, then) => {
+                let rhs = self.build(rhs, locals);
+                locals.push(rhs);
+                let then = self.build(then, locals);
+                locals.pop();
+                then
+            }
+            Expr::Set(local, rhs) => {
+                let rhs = self.build(rhs, locals);
+                let len = locals.len();
+                locals[len - local - 1] = rhs;
+                self.emit(Inst::Litr(UNIT))
+            }
+            Expr::While(pred, body) => {
+                let entry = self.current;
+                let header = self.new_block();
+                self.terminate(Term::Jmp(header));
+                self.current = header;
+
+                // The body may change any local, so each gets a phi. The ones that turn out not to change are removed
+                // afterwards.
+                let phis = locals
+                    .iter_mut()
+                    .map(|local| {
+                        let phi = self.emit(Inst::Phi(vec![(entry, *local)]));
+                        *local = phi;
+                        phi
+                    })
+                    .collect::<Vec<_>>();
+
+                let pred = self.build(pred, locals);
+                let (body_start, exit) = (self.new_block(), self.new_block());
+                self.terminate(Term::Branch(pred, body_start, exit));
+                let exit_locals = locals.clone();
+
+                self.current = body_start;
+                self.build(body, locals);
+                self.terminate(Term::Jmp(header));
+                for (phi, local) in phis.into_iter().zip(locals.iter()) {
+                    if let Inst::Phi(preds) = &mut self.insts[phi] {
+                        preds.push((self.current, *local));
+                    }
+                }
+
+                *locals = exit_locals;
+                self.current = exit;
+                self.emit(Inst::Litr(UNIT))
+            }
+            Expr::Then(a, b) => {
+                self.build(a, locals);
+                self.build(b, locals)
+            }
+        }
+    }
+}
+
+impl Ir {
+    /// Build the IR for an expression.
+    pub fn new(expr: &Expr) -> Self {
+        let mut builder = Builder {
+            insts: Vec::new(),
+            blocks: vec![(Vec::new(), None)],
+            current: 0,
+        };
+        let result = builder.build(expr, &mut Vec::new());
+        builder.terminate(Term::Ret(result));
+
+        let mut ir = Ir {
+            insts: builder.insts,
+            blocks: builder
+                .blocks
+                .into_iter()
+                .map(|(insts, term)| Block {
+                    insts,
+                    term: term.unwrap(),
+                })
+                .collect(),
+        };
+        ir.remove_trivial_phis();
+        ir
+    }
+
+    // Remove phis that only ever take one value (other than their own), replacing them with that value. Removing one
+    // phi can make others trivial, so repeat until there are none left.
+    fn remove_trivial_phis(&mut self) {
+        let mut replace = (0..self.insts.len()).collect::<Vec<_>>();
+        fn resolve(replace: &[Value], mut v: Value) -> Value {
+            while replace[v] != v {
+                v = replace[v];
+            }
+            v
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in &mut self.blocks {
+                block.insts.retain(|&phi| {
+                    let Inst::Phi(preds) = &self.insts[phi] else {
+                        return true;
+                    };
+                    let mut values = preds
+                        .iter()
+                        .map(|(_, v)| resolve(&replace, *v))
+                        .filter(|v| *v != phi);
+                    let Some(first) = values.next() else {
+                        return true;
+                    };
+                    if values.all(|v| v == first) {
+                        replace[phi] = first;
+                        changed = true;
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+        }
+
+        for inst in &mut self.insts {
+            for v in inst.operands_mut() {
+                *v = resolve(&replace, *v);
+            }
+        }
+        for block in &mut self.blocks {
+            if let Some(v) = block.term.operand_mut() {
+                *v = resolve(&replace, *v);
+            }
+        }
+    }
+
+    /// How many times each value is used, by instructions still in a block, or by terminators.
+    pub fn use_counts(&self) -> Vec<usize> {
+        let mut uses = vec![0; self.insts.len()];
+        for block in &self.blocks {
+            for &v in &block.insts {
+                for operand in self.insts[v].operands() {
+                    uses[operand] += 1;
+                }
+            }
+            if let Term::Branch(v, _, _) | Term::Ret(v) = block.term {
+                uses[v] += 1;
+            }
+        }
+        uses
+    }
+
+    /// The phis at the start of a block.
+    pub fn phis(&self, block: BlockId) -> impl Iterator<Item = Value> + '_ {
+        self.blocks[block]
+            .insts
+            .iter()
+            .copied()
+            .take_while(|&v| matches!(self.insts[v], Inst::Phi(_)))
+    }
+
+    // The value that each phi of `to` takes when control comes from `from`
+    fn phi_moves(&self, from: BlockId, to: BlockId) -> Vec<(Value, Value)> {
+        self.phis(to)
+            .filter_map(|phi| match &self.insts[phi] {
+                Inst::Phi(preds) => preds
+                    .iter()
+                    .find(|(pred, _)| *pred == from)
+                    .map(|(_, v)| (phi, *v)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Ir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "b{id}:")?;
+            for &v in &block.insts {
+                match &self.insts[v] {
+                    Inst::Litr(x) => writeln!(f, "  v{v} = {x}")?,
+                    Inst::Arg(idx) => writeln!(f, "  v{v} = arg {idx}")?,
+                    Inst::Add(x, y) => writeln!(f, "  v{v} = v{x} + v{y}")?,
+                    Inst::Phi(preds) => {
+                        write!(f, "  v{v} = phi")?;
+                        for (i, (pred, x)) in preds.iter().enumerate() {
+                            let sep = if i == 0 { " " } else { ", " };
+                            write!(f, "{sep}[b{pred}: v{x}]")?;
+                        }
+                        writeln!(f)?;
+                    }
+                }
+            }
+            match block.term {
+                Term::Jmp(to) => writeln!(f, "  jmp b{to}")?,
+                Term::Branch(v, then, els) => writeln!(f, "  br v{v}, b{then}, b{els}")?,
+                Term::Ret(v) => writeln!(f, "  ret v{v}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Where each value lives once the IR is lowered to a backend with locals. Values that are used exactly once, in the
+// block that defines them, are computed where they're used. Everything else (phis, and values that are shared or live
+// across blocks) is stored in a slot of its own.
+struct Slots {
+    slot: Vec<Option<usize>>,
+    count: usize,
+}
+
+impl Slots {
+    fn new(ir: &Ir) -> Self {
+        let uses = ir.use_counts();
+        let mut def_block = vec![usize::MAX; ir.insts.len()];
+        for (id, block) in ir.blocks.iter().enumerate() {
+            for &v in &block.insts {
+                def_block[v] = id;
+            }
+        }
+        // Phi operands count as being used at the end of the predecessor they come from
+        let mut used_elsewhere = vec![false; ir.insts.len()];
+        for (id, block) in ir.blocks.iter().enumerate() {
+            for &v in &block.insts {
+                match &ir.insts[v] {
+                    Inst::Phi(preds) => {
+                        for (pred, x) in preds {
+                            used_elsewhere[*x] |= def_block[*x] != *pred;
+                        }
+                    }
+                    inst => {
+                        for x in inst.operands() {
+                            used_elsewhere[x] |= def_block[x] != id;
+                        }
+                    }
+                }
+            }
+            if let Term::Branch(v, _, _) | Term::Ret(v) = block.term {
+                used_elsewhere[v] |= def_block[v] != id;
+            }
+        }
+
+        let mut slots = Slots {
+            slot: vec![None; ir.insts.len()],
+            count: 0,
+        };
+        for block in &ir.blocks {
+            for &v in &block.insts {
+                let is_phi = matches!(ir.insts[v], Inst::Phi(_));
+                if uses[v] > 0 && (is_phi || uses[v] > 1 || used_elsewhere[v]) {
+                    slots.slot[v] = Some(slots.count);
+                    slots.count += 1;
+                }
+            }
+        }
+        slots
+    }
+}
+
+/// A backend that can compile programs from the IR.
+pub trait FromIr: Vm {
+    fn compile_ir(ir: &Ir) -> Self::Program<'static>;
+}
+
+impl FromIr for Bytecode {
+    fn compile_ir(ir: &Ir) -> Self::Program<'static> {
+        struct Lowering<'a> {
+            ir: &'a Ir,
+            slots: Slots,
+            ops: Vec<Op>,
+            depth: usize,
+            max_depth: usize,
+        }
+
+        impl Lowering<'_> {
+            fn push(&mut self, op: Op) {
+                match op {
+                    Op::Litr(_) | Op::Arg(_) | Op::Get(_) => {
+                        self.depth += 1;
+                        self.max_depth = self.max_depth.max(self.depth);
+                    }
+                    Op::Add | Op::PushLocal | Op::SetLocal(_) | Op::JmpZN(_) | Op::Ret => {
+                        self.depth -= 1
+                    }
+                    Op::PopLocal | Op::Pop | Op::Jmp(_) => {}
+                }
+                self.ops.push(op);
+            }
+
+            // Every slot is a local, pushed on entry, so its offset from the top never changes
+            fn local(&self, slot: usize) -> LocalOffset {
+                self.slots.count - slot - 1
+            }
+
+            fn value(&mut self, v: Value) {
+                match self.slots.slot[v] {
+                    Some(slot) => self.push(Op::Get(self.local(slot))),
+                    None => self.compute(v),
+                }
+            }
+
+            fn compute(&mut self, v: Value) {
+                match self.ir.insts[v] {
+                    Inst::Litr(x) => self.push(Op::Litr(x)),
+                    Inst::Arg(idx) => self.push(Op::Arg(idx)),
+                    Inst::Add(x, y) => {
+                        self.value(x);
+                        self.value(y);
+                        self.push(Op::Add);
+                    }
+                    Inst::Phi(_) => unreachable!("phis always have a slot"),
+                }
+            }
+
+            // Move values into the phis of `to`, all at once, since phis may refer to each other
+            fn moves(&mut self, from: BlockId, to: BlockId) {
+                let moves = self.ir.phi_moves(from, to);
+                let moves = moves
+                    .into_iter()
+                    .filter_map(|(phi, v)| Some((self.slots.slot[phi]?, v)))
+                    .collect::<Vec<_>>();
+                for &(_, v) in &moves {
+                    self.value(v);
+                }
+                for &(slot, _) in moves.iter().rev() {
+                    self.push(Op::SetLocal(self.local(slot)));
+                }
+            }
+        }
+
+        let mut lowering = Lowering {
+            slots: Slots::new(ir),
+            ir,
+            ops: Vec::new(),
+            depth: 0,
+            max_depth: 0,
+        };
+        for _ in 0..lowering.slots.count {
+            lowering.push(Op::Litr(0));
+            lowering.push(Op::PushLocal);
+        }
+
+        let mut labels = Vec::new();
+        // (op, block) for each jump to a block
+        let mut fixups = Vec::new();
+        for (id, block) in ir.blocks.iter().enumerate() {
+            labels.push(lowering.ops.len());
+            for &v in &block.insts {
+                if let (Some(slot), false) =
+                    (lowering.slots.slot[v], matches!(ir.insts[v], Inst::Phi(_)))
+                {
+                    lowering.compute(v);
+                    lowering.push(Op::SetLocal(lowering.local(slot)));
+                }
+            }
+            // Jump to a block, unless it comes next anyway
+            let mut jump = |lowering: &mut Lowering, to: BlockId| {
+                lowering.moves(id, to);
+                if to != id + 1 {
+                    fixups.push((lowering.ops.len(), to));
+                    lowering.push(Op::Jmp(0));
+                }
+            };
+            match block.term {
+                Term::Jmp(to) => jump(&mut lowering, to),
+                Term::Branch(cond, then, els) => {
+                    lowering.value(cond);
+                    let branch = lowering.ops.len();
+                    lowering.push(Op::JmpZN(0)); // Will be fixed up
+                    if ir.phi_moves(id, els).is_empty() {
+                        jump(&mut lowering, then);
+                        fixups.push((branch, els));
+                    } else {
+                        // `els`'s moves come next, so `then` can't be fallen through to even if it's the next block
+                        lowering.moves(id, then);
+                        fixups.push((lowering.ops.len(), then));
+                        lowering.push(Op::Jmp(0));
+                        lowering.ops[branch] = Op::JmpZN(lowering.ops.len());
+                        lowering.moves(id, els);
+                        fixups.push((lowering.ops.len(), els));
+                        lowering.push(Op::Jmp(0));
+                    }
+                }
+                Term::Ret(v) => {
+                    lowering.value(v);
+                    lowering.push(Op::Ret);
+                }
+            }
+        }
+        for (op, block) in fixups {
+            match &mut lowering.ops[op] {
+                Op::Jmp(goto) | Op::JmpZN(goto) => *goto = labels[block],
+                _ => unreachable!(),
+            }
+        }
+
+        Frame {
+            max_locals: lowering.slots.count,
+            max_stack: lowering.max_depth,
+            code: lowering.ops,
+        }
+    }
+}
+
+impl FromIr for Closures {
+    fn compile_ir(ir: &Ir) -> Self::Program<'static> {
+        use crate::closures::Func;
+
+        enum Flow {
+            Jmp(BlockId),
+            Ret(i64),
+        }
+        type BlockFunc = Box<dyn Fn(*const i64, *mut i64) -> Flow + Send + Sync>;
+
+        let slots = Slots::new(ir);
+
+        // Slots are addressed from the bottom of the locals, since they're all allocated up front
+        fn value(ir: &Ir, slots: &Slots, v: Value) -> Func<'static> {
+            if let Some(slot) = slots.slot[v] {
+                return make_func(move |_, locals| unsafe { *locals.add(slot) });
+            }
+            compute(ir, slots, v)
+        }
+
+        fn compute(ir: &Ir, slots: &Slots, v: Value) -> Func<'static> {
+            match ir.insts[v] {
+                Inst::Litr(x) => make_func(move |_, _| x),
+                Inst::Arg(idx) => make_func(move |args, _| unsafe { *args.add(idx) }),
+                Inst::Add(x, y) => match ir.insts[y] {
+                    Inst::Litr(y) if slots.slot[v].is_none() => {
+                        let x = value(ir, slots, x);
+                        make_func(move |args, locals| x.invoke(args, locals) + y)
+                    }
+                    _ => {
+                        let (x, y) = (value(ir, slots, x), value(ir, slots, y));
+                        make_func(move |args, locals| {
+                            x.invoke(args, locals) + y.invoke(args, locals)
+                        })
+                    }
+                },
+                Inst::Phi(_) => unreachable!("phis always have a slot"),
+            }
+        }
+
+        // Phis may refer to each other, so every value is computed into scratch slots (above the others) before any
+        // phi is overwritten
+        let scratch = slots.count;
+        let mut max_moves = 0;
+        let mut moves = |from: BlockId, to: BlockId| {
+            let moves = ir
+                .phi_moves(from, to)
+                .into_iter()
+                .filter_map(|(phi, v)| Some((slots.slot[phi]?, value(ir, &slots, v))))
+                .collect::<Vec<_>>();
+            max_moves = max_moves.max(moves.len());
+            move |args, locals: *mut i64| unsafe {
+                for (i, (_, v)) in moves.iter().enumerate() {
+                    *locals.add(scratch + i) = v.invoke(args, locals);
+                }
+                for (i, (slot, _)) in moves.iter().enumerate() {
+                    *locals.add(*slot) = *locals.add(scratch + i);
+                }
+            }
+        };
+
+        let blocks = ir
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(id, block)| -> BlockFunc {
+                let stores = block
+                    .insts
+                    .iter()
+                    .filter_map(|&v| match (&ir.insts[v], slots.slot[v]) {
+                        (Inst::Phi(_), _) | (_, None) => None,
+                        (_, Some(slot)) => Some((slot, compute(ir, &slots, v))),
+                    })
+                    .collect::<Vec<_>>();
+                let store = move |args, locals: *mut i64| unsafe {
+                    for (slot, v) in &stores {
+                        *locals.add(*slot) = v.invoke(args, locals);
+                    }
+                };
+                match block.term {
+                    Term::Jmp(to) => {
+                        let moves = moves(id, to);
+                        Box::new(move |args, locals| {
+                            store(args, locals);
+                            moves(args, locals);
+                            Flow::Jmp(to)
+                        })
+                    }
+                    Term::Branch(cond, then, els) => {
+                        let cond = value(ir, &slots, cond);
+                        let (then_moves, els_moves) = (moves(id, then), moves(id, els));
+                        Box::new(move |args, locals| {
+                            store(args, locals);
+                            if cond.invoke(args, locals) > 0 {
+                                then_moves(args, locals);
+                                Flow::Jmp(then)
+                            } else {
+                                els_moves(args, locals);
+                                Flow::Jmp(els)
+                            }
+                        })
+                    }
+                    Term::Ret(v) => {
+                        let v = value(ir, &slots, v);
+                        Box::new(move |args, locals| {
+                            store(args, locals);
+                            Flow::Ret(v.invoke(args, locals))
+                        })
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Frame {
+            code: make_func(move |args, locals| {
+                let mut block = 0;
+                loop {
+                    match blocks[block](args, locals) {
+                        Flow::Jmp(to) => block = to,
+                        Flow::Ret(x) => return x,
+                    }
+                }
+            }),
+            max_locals: scratch + max_moves,
+            max_stack: 0, // Intermediate values live on the hardware stack
+        }
+    }
+}
+
+/// Compiles programs to the IR, and from there with `V`.
+pub struct Ssa<V>(PhantomData<fn() -> V>);
+
+impl<V> Ssa<V> {
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<V> Default for Ssa<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: FromIr> Vm for Ssa<V> {
+    type Program<'a> = V::Program<'static>;
+    type Context = V::Context;
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        V::compile_ir(&Ir::new(expr))
+    }
+
+    fn new_context() -> Self::Context {
+        V::new_context()
+    }
+
+    unsafe fn execute_in(ctx: &mut Self::Context, prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+        V::execute_in(ctx, prog, args)
+    }
+}
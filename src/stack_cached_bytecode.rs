@@ -0,0 +1,229 @@
+use super::*;
+use crate::bytecode::Op;
+
+// `Bytecode`, but with the top two values of the stack cached in locals (and so, hopefully, registers). Every op has a
+// handler for each state of the cache, so most ops never touch the stack in memory at all.
+//
+// This is the static variant of the stack caching described by Ertl: the depth of the stack is known at every op, so
+// the state of the cache is too, and the state machine runs at compile time to pick each op's handler. Dispatch is then
+// a single `match`, rather than one on the op and another on the state. The cache always holds as many values as it can
+// (the top two, or the whole stack if it's shallower), so jump targets agree on its state wherever they're reached from.
+pub struct StackCachedBytecode;
+
+// Handlers are suffixed with the number of values cached before they run, or with `Refill` if they pop a value and
+// another has to be brought into the cache from memory afterwards. With one value cached, it's in `a`. With two, `a` is
+// the top of the stack and `b` is below it.
+#[derive(Copy, Clone, Debug)]
+pub enum CachedOp {
+    Litr0(i64),
+    Litr1(i64),
+    Litr2(i64),
+    Arg0(usize),
+    Arg1(usize),
+    Arg2(usize),
+    Get0(usize),
+    Get1(usize),
+    Get2(usize),
+    Add2,
+    AddRefill,
+    PushLocal1,
+    PushLocal2,
+    PushLocalRefill,
+    PopLocal,
+    SetLocal1(usize),
+    SetLocal2(usize),
+    SetLocalRefill(usize),
+    Pop1,
+    Pop2,
+    PopRefill,
+    JmpZN1(usize),
+    JmpZN2(usize),
+    JmpZNRefill(usize),
+    Jmp(usize),
+    Ret,
+}
+
+impl CachedOp {
+    // Pick the handler for an op, given the depth of the stack before it runs
+    fn specialise(op: Op, depth: usize) -> Self {
+        match (op, depth) {
+            (Op::Litr(x), 0) => Self::Litr0(x),
+            (Op::Litr(x), 1) => Self::Litr1(x),
+            (Op::Litr(x), _) => Self::Litr2(x),
+            (Op::Arg(idx), 0) => Self::Arg0(idx),
+            (Op::Arg(idx), 1) => Self::Arg1(idx),
+            (Op::Arg(idx), _) => Self::Arg2(idx),
+            (Op::Get(local), 0) => Self::Get0(local),
+            (Op::Get(local), 1) => Self::Get1(local),
+            (Op::Get(local), _) => Self::Get2(local),
+            (Op::Add, 2) => Self::Add2,
+            (Op::Add, _) => Self::AddRefill,
+            (Op::PushLocal, 1) => Self::PushLocal1,
+            (Op::PushLocal, 2) => Self::PushLocal2,
+            (Op::PushLocal, _) => Self::PushLocalRefill,
+            (Op::PopLocal, _) => Self::PopLocal,
+            (Op::SetLocal(local), 1) => Self::SetLocal1(local),
+            (Op::SetLocal(local), 2) => Self::SetLocal2(local),
+            (Op::SetLocal(local), _) => Self::SetLocalRefill(local),
+            (Op::Pop, 1) => Self::Pop1,
+            (Op::Pop, 2) => Self::Pop2,
+            (Op::Pop, _) => Self::PopRefill,
+            (Op::JmpZN(goto), 1) => Self::JmpZN1(goto),
+            (Op::JmpZN(goto), 2) => Self::JmpZN2(goto),
+            (Op::JmpZN(goto), _) => Self::JmpZNRefill(goto),
+            (Op::Jmp(goto), _) => Self::Jmp(goto),
+            (Op::Ret, _) => Self::Ret,
+        }
+    }
+}
+
+impl Vm for StackCachedBytecode {
+    type Program<'a> = Frame<Vec<CachedOp>>;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        let prog = Bytecode::compile(expr);
+
+        // Code is structured, so walking the ops in order tracks the depth of the stack at each one. Ops after a `Jmp`
+        // are only reached from the `JmpZN` at the top of the loop, which left the stack at the same depth.
+        let mut depth = 0;
+        let code = prog
+            .code
+            .iter()
+            .map(|&op| {
+                let cached = CachedOp::specialise(op, depth);
+                match op {
+                    Op::Litr(_) | Op::Arg(_) | Op::Get(_) => depth += 1,
+                    Op::Add | Op::PushLocal | Op::SetLocal(_) | Op::Pop | Op::JmpZN(_) => {
+                        depth -= 1
+                    }
+                    Op::PopLocal | Op::Jmp(_) | Op::Ret => {}
+                }
+                cached
+            })
+            .collect();
+
+        Frame {
+            code,
+            max_locals: prog.max_locals,
+            max_stack: prog.max_stack,
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
+    }
+
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        let mut ip = 0;
+        let (mut a, mut b) = (0, 0);
+        stack.clear();
+        locals.clear();
+        stack.reserve(prog.max_stack);
+        locals.reserve(prog.max_locals);
+
+        // Push a value, given how many values are cached
+        macro_rules! push {
+            (0, $x:expr) => {
+                a = $x
+            };
+            (1, $x:expr) => {{
+                b = a;
+                a = $x;
+            }};
+            (2, $x:expr) => {{
+                // Spill the bottom of the cache
+                stack.push(b);
+                b = a;
+                a = $x;
+            }};
+        }
+        // Pop the top of the cache, refilling it from memory if there's more of the stack there
+        macro_rules! pop {
+            (1) => {{}};
+            (2) => {
+                a = b
+            };
+            (Refill) => {{
+                a = b;
+                b = stack.pop().unwrap_unchecked();
+            }};
+        }
+        macro_rules! local {
+            ($local:expr) => {
+                *{
+                    let local_offs = locals.len() - $local - 1;
+                    locals.get_unchecked_mut(local_offs)
+                }
+            };
+        }
+        loop {
+            let op = *prog.code.get_unchecked(ip);
+            ip += 1;
+            match op {
+                CachedOp::Litr0(x) => push!(0, x),
+                CachedOp::Litr1(x) => push!(1, x),
+                CachedOp::Litr2(x) => push!(2, x),
+                CachedOp::Arg0(idx) => push!(0, *args.get_unchecked(idx)),
+                CachedOp::Arg1(idx) => push!(1, *args.get_unchecked(idx)),
+                CachedOp::Arg2(idx) => push!(2, *args.get_unchecked(idx)),
+                CachedOp::Get0(local) => push!(0, local!(local)),
+                CachedOp::Get1(local) => push!(1, local!(local)),
+                CachedOp::Get2(local) => push!(2, local!(local)),
+                CachedOp::Add2 => a += b,
+                CachedOp::AddRefill => {
+                    a += b;
+                    b = stack.pop().unwrap_unchecked();
+                }
+                CachedOp::PushLocal1 => locals.push(a),
+                CachedOp::PushLocal2 => {
+                    locals.push(a);
+                    pop!(2);
+                }
+                CachedOp::PushLocalRefill => {
+                    locals.push(a);
+                    pop!(Refill);
+                }
+                CachedOp::PopLocal => {
+                    locals.pop().unwrap_unchecked();
+                }
+                CachedOp::SetLocal1(local) => local!(local) = a,
+                CachedOp::SetLocal2(local) => {
+                    local!(local) = a;
+                    pop!(2);
+                }
+                CachedOp::SetLocalRefill(local) => {
+                    local!(local) = a;
+                    pop!(Refill);
+                }
+                CachedOp::Pop1 => pop!(1),
+                CachedOp::Pop2 => pop!(2),
+                CachedOp::PopRefill => pop!(Refill),
+                CachedOp::JmpZN1(goto) => {
+                    if a <= 0 {
+                        ip = goto;
+                    }
+                }
+                CachedOp::JmpZN2(goto) => {
+                    if a <= 0 {
+                        ip = goto;
+                    }
+                    pop!(2);
+                }
+                CachedOp::JmpZNRefill(goto) => {
+                    if a <= 0 {
+                        ip = goto;
+                    }
+                    pop!(Refill);
+                }
+                CachedOp::Jmp(goto) => ip = goto,
+                // The stack is balanced by now, so the result is the only value on it
+                CachedOp::Ret => break a,
+            }
+        }
+    }
+}
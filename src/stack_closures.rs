@@ -2,47 +2,49 @@ use super::*;
 
 pub struct StackClosures;
 
-type OpFn<'a> = Box<
+type OpFn = Box<
     dyn Fn(
             &[i64],        // args
             &mut usize,    // ip
             &mut Vec<i64>, // stack
             &mut Vec<i64>, // locals
         ) -> Option<i64>
-        + 'a,
+        + Send
+        + Sync,
 >;
 
 impl Vm for StackClosures {
-    type Program<'a> = Vec<OpFn<'a>>;
+    type Program<'a> = Frame<Vec<OpFn>>;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
-        fn returns(expr: &Expr) -> bool {
+        fn compile_inner(ops: &mut Vec<OpFn>, expr: &Expr) {
             match expr {
-                Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) | Expr::Add(_, _) => true,
-                Expr::Let(_, expr) => returns(expr),
-                Expr::Set(_, _) | Expr::While(_, _) => false,
-                Expr::Then(_, b) => returns(b),
-            }
-        }
-
-        fn compile_inner<'a>(ops: &mut Vec<OpFn<'a>>, expr: &'a Expr) {
-            match expr {
-                Expr::Litr(x) => ops.push(Box::new(move |_, _, stack, _| {
-                    stack.push(*x);
-                    None
-                })),
-                Expr::Arg(idx) => ops.push(Box::new(move |args, _, stack, _| {
-                    unsafe {
-                        stack.push(*args.get_unchecked(*idx));
-                    }
-                    None
-                })),
-                Expr::Get(local) => ops.push(Box::new(move |_, _, stack, locals| {
-                    unsafe {
-                        stack.push(*locals.get_unchecked(locals.len() - local - 1));
-                    }
-                    None
-                })),
+                Expr::Litr(x) => {
+                    let x = *x;
+                    ops.push(Box::new(move |_, _, stack, _| {
+                        stack.push(x);
+                        None
+                    }))
+                }
+                Expr::Arg(idx) => {
+                    let idx = *idx;
+                    ops.push(Box::new(move |args, _, stack, _| {
+                        unsafe {
+                            stack.push(*args.get_unchecked(idx));
+                        }
+                        None
+                    }))
+                }
+                Expr::Get(local) => {
+                    let local = *local;
+                    ops.push(Box::new(move |_, _, stack, locals| {
+                        unsafe {
+                            stack.push(*locals.get_unchecked(locals.len() - local - 1));
+                        }
+                        None
+                    }))
+                }
                 Expr::Add(x, y) => {
                     compile_inner(ops, x);
                     compile_inner(ops, y);
@@ -73,6 +75,7 @@ impl Vm for StackClosures {
                     }));
                 }
                 Expr::Set(local, rhs) => {
+                    let local = *local;
                     compile_inner(ops, rhs);
                     ops.push(Box::new(move |_, _, stack, locals| {
                         unsafe {
@@ -89,7 +92,7 @@ impl Vm for StackClosures {
                     let branch_fixup = ops.len();
                     ops.push(Box::new(move |_, _, _, _| None));
                     compile_inner(ops, body);
-                    if returns(body) {
+                    if body.returns() {
                         ops.push(Box::new(move |_, _, stack, _| {
                             unsafe {
                                 stack.pop().unwrap_unchecked();
@@ -114,7 +117,7 @@ impl Vm for StackClosures {
                 }
                 Expr::Then(a, b) => {
                     compile_inner(ops, a);
-                    if returns(a) {
+                    if a.returns() {
                         ops.push(Box::new(move |_, _, stack, _| {
                             unsafe {
                                 stack.pop().unwrap_unchecked();
@@ -135,17 +138,31 @@ impl Vm for StackClosures {
             Some(stack.pop().unwrap_unchecked())
         }));
 
-        ops
+        Frame {
+            code: ops,
+            max_locals: expr.max_locals(),
+            max_stack: expr.max_stack(),
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
     }
 
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
         let mut ip = 0;
-        let mut stack = Vec::new();
-        let mut locals = Vec::new();
+        stack.clear();
+        locals.clear();
+        stack.reserve(prog.max_stack);
+        locals.reserve(prog.max_locals);
         loop {
-            let f = prog.get_unchecked(ip);
+            let f = prog.code.get_unchecked(ip);
             ip += 1;
-            if let Some(res) = f(args, &mut ip, &mut stack, &mut locals) {
+            if let Some(res) = f(args, &mut ip, stack, locals) {
                 break res;
             }
         }
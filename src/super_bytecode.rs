@@ -0,0 +1,340 @@
+use super::*;
+use crate::bytecode::Op;
+use std::collections::HashMap;
+
+// `Bytecode`, but with common sequences of ops fused into superinstructions so that they only need one dispatch.
+//
+// Which sequences get fused is decided by profiling: `Profile` records how often each straight-line sequence of ops is
+// executed across a set of workloads, and `Profile::table` turns the most valuable ones into the `superinstructions!`
+// table at the bottom of this file. Run `cargo run --release --example superinstructions` to regenerate it.
+pub struct SuperBytecode;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum OpKind {
+    Litr,
+    Arg,
+    Get,
+    Add,
+    PushLocal,
+    PopLocal,
+    SetLocal,
+    Pop,
+    JmpZN,
+    Jmp,
+    Ret,
+}
+
+impl OpKind {
+    pub fn of(op: &Op) -> Self {
+        match op {
+            Op::Litr(_) => Self::Litr,
+            Op::Arg(_) => Self::Arg,
+            Op::Get(_) => Self::Get,
+            Op::Add => Self::Add,
+            Op::PushLocal => Self::PushLocal,
+            Op::PopLocal => Self::PopLocal,
+            Op::SetLocal(_) => Self::SetLocal,
+            Op::Pop => Self::Pop,
+            Op::JmpZN(_) => Self::JmpZN,
+            Op::Jmp(_) => Self::Jmp,
+            Op::Ret => Self::Ret,
+        }
+    }
+
+    // Control flow can't be fused, since a superinstruction always falls through to the next op
+    fn fusable(self) -> bool {
+        !matches!(self, Self::JmpZN | Self::Jmp | Self::Ret)
+    }
+}
+
+// How often each straight-line run of fusable ops was executed
+#[derive(Default)]
+pub struct Profile {
+    runs: HashMap<Vec<OpKind>, u64>,
+}
+
+impl Profile {
+    // The longest sequence that gets considered for fusion
+    pub const MAX_LEN: usize = 4;
+
+    /// Execute a `Bytecode` program, recording each run of ops that executes between jumps and jump targets.
+    ///
+    /// # Safety
+    ///
+    /// `prog` must have been produced by `Bytecode::compile`, and `args` must hold a value for every argument it reads.
+    pub unsafe fn record(&mut self, prog: &<Bytecode as Vm>::Program<'_>, args: &[i64]) -> i64 {
+        let mut is_target = vec![false; prog.code.len()];
+        for op in &prog.code {
+            if let Op::JmpZN(goto) | Op::Jmp(goto) = op {
+                is_target[*goto] = true;
+            }
+        }
+
+        let (mut stack, mut locals) = Bytecode::new_context();
+        let mut run = Vec::new();
+        let mut ip = 0;
+        loop {
+            let op = prog.code.get_unchecked(ip);
+            let kind = OpKind::of(op);
+            if is_target[ip] || !kind.fusable() {
+                self.end_run(&mut run);
+            }
+            if kind.fusable() {
+                run.push(kind);
+            }
+            ip += 1;
+            if let Some(res) = Bytecode::step(op, &mut ip, args, &mut stack, &mut locals) {
+                break res;
+            }
+        }
+    }
+
+    fn end_run(&mut self, run: &mut Vec<OpKind>) {
+        if run.len() > 1 {
+            *self.runs.entry(run.clone()).or_default() += 1;
+        }
+        run.clear();
+    }
+
+    // Choose `k` sequences to fuse, along with how many dispatches each would save. Sequences are chosen greedily: once
+    // a sequence is chosen, it gets fused wherever it occurs so that overlapping sequences aren't counted twice.
+    pub fn top(&self, k: usize) -> Vec<(Vec<OpKind>, u64)> {
+        // Each run as a list of instructions, which start out unfused
+        let mut runs = self
+            .runs
+            .iter()
+            .map(|(run, n)| (run.iter().map(|kind| vec![*kind]).collect::<Vec<_>>(), *n))
+            .collect::<Vec<_>>();
+
+        // Find each non-overlapping occurrence of `seq` among the unfused instructions of `run`
+        fn occurrences(run: &[Vec<OpKind>], seq: &[OpKind]) -> Vec<usize> {
+            let mut found = Vec::new();
+            let mut i = 0;
+            while i + seq.len() <= run.len() {
+                if run[i..i + seq.len()]
+                    .iter()
+                    .zip(seq)
+                    .all(|(insn, kind)| insn[..] == [*kind])
+                {
+                    found.push(i);
+                    i += seq.len();
+                } else {
+                    i += 1;
+                }
+            }
+            found
+        }
+
+        let mut chosen = Vec::new();
+        while chosen.len() < k {
+            let mut candidates = runs
+                .iter()
+                .flat_map(|(run, _)| (2..=Self::MAX_LEN).flat_map(move |len| run.windows(len)))
+                .filter(|window| window.iter().all(|insn| insn.len() == 1))
+                .map(|window| window.iter().map(|insn| insn[0]).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            candidates.sort();
+            candidates.dedup();
+
+            let saved = |seq: &[OpKind]| {
+                runs.iter()
+                    .map(|(run, n)| n * occurrences(run, seq).len() as u64)
+                    .sum::<u64>()
+                    * (seq.len() as u64 - 1)
+            };
+            // Prefer the longest sequence on a tie, then keep the order deterministic
+            let Some((seq, saved)) = candidates
+                .into_iter()
+                .map(|seq| {
+                    let saved = saved(&seq);
+                    (seq, saved)
+                })
+                .max_by(|(a, x), (b, y)| x.cmp(y).then(a.len().cmp(&b.len())).then(b.cmp(a)))
+            else {
+                break;
+            };
+
+            for (run, _) in &mut runs {
+                for i in occurrences(run, &seq).into_iter().rev() {
+                    run.splice(i..i + seq.len(), [seq.clone()]);
+                }
+            }
+            chosen.push((seq, saved));
+        }
+        chosen
+    }
+
+    // Generate a `superinstructions!` table for the top `k` sequences
+    pub fn table(&self, k: usize) -> String {
+        let mut table = String::from("superinstructions! {\n");
+        for (seq, saved) in self.top(k) {
+            let kinds = seq
+                .iter()
+                .map(|kind| format!("{kind:?}"))
+                .collect::<Vec<_>>();
+            table += &format!(
+                "    {}: [{}], // {saved} dispatches saved\n",
+                kinds.concat(),
+                kinds.join(", ")
+            );
+        }
+        table += "}\n";
+        table
+    }
+}
+
+// Perform the op of the given kind, taking its operand slot from `$operands` (ops without an operand ignore theirs)
+macro_rules! step {
+    ($kind:ident, $operands:ident, $args:ident, $stack:ident, $locals:ident) => {{
+        let operand = *$operands.next().unwrap_unchecked();
+        let op = step!(@op $kind, operand);
+        Bytecode::step(&op, &mut 0, $args, $stack, $locals);
+    }};
+    (@op Litr, $x:expr) => { Op::Litr($x as i64) };
+    (@op Arg, $x:expr) => { Op::Arg($x) };
+    (@op Get, $x:expr) => { Op::Get($x) };
+    (@op Add, $x:expr) => {{ let _ = $x; Op::Add }};
+    (@op PushLocal, $x:expr) => {{ let _ = $x; Op::PushLocal }};
+    (@op PopLocal, $x:expr) => {{ let _ = $x; Op::PopLocal }};
+    (@op SetLocal, $x:expr) => { Op::SetLocal($x) };
+    (@op Pop, $x:expr) => {{ let _ = $x; Op::Pop }};
+}
+
+// Each superinstruction stores one operand slot per fused op. Since the kinds of the fused ops are known statically,
+// `Bytecode::step` gets inlined and specialised for each of them.
+macro_rules! superinstructions {
+    ($($name:ident: [$($kind:ident),+],)*) => {
+        #[derive(Debug)]
+        pub enum SuperOp {
+            Op(Op),
+            $($name([usize; [$(OpKind::$kind),+].len()]),)*
+        }
+
+        const SUPERINSTRUCTIONS: &[&[OpKind]] = &[$(&[$(OpKind::$kind),+],)*];
+
+        impl SuperOp {
+            // Create the superinstruction at the given index of `SUPERINSTRUCTIONS`
+            fn fused(idx: usize, ops: &[Op]) -> Self {
+                let mut operands = ops.iter().map(|op| match op {
+                    Op::Litr(x) => *x as usize,
+                    Op::Arg(x) | Op::Get(x) | Op::SetLocal(x) => *x,
+                    _ => 0,
+                });
+                let mut i = 0;
+                $(
+                    if i == idx {
+                        return Self::$name(core::array::from_fn(|_| operands.next().unwrap()));
+                    }
+                    i += 1;
+                )*
+                unreachable!("no superinstruction {i}")
+            }
+
+            #[inline(always)]
+            unsafe fn step(
+                &self,
+                ip: &mut usize,
+                args: &[i64],
+                stack: &mut Vec<i64>,
+                locals: &mut Vec<i64>,
+            ) -> Option<i64> {
+                match self {
+                    Self::Op(op) => Bytecode::step(op, ip, args, stack, locals),
+                    $(Self::$name(operands) => {
+                        let mut operands = operands.iter();
+                        $(step!($kind, operands, args, stack, locals);)+
+                        None
+                    })*
+                }
+            }
+        }
+    };
+}
+
+impl Vm for SuperBytecode {
+    type Program<'a> = Frame<Vec<SuperOp>>;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        let prog = Bytecode::compile(expr);
+        let ops = prog.code;
+
+        // Sequences can't be fused if something jumps into the middle of them
+        let mut is_target = vec![false; ops.len() + 1];
+        for op in &ops {
+            if let Op::JmpZN(goto) | Op::Jmp(goto) = op {
+                is_target[*goto] = true;
+            }
+        }
+
+        // Greedily fuse sequences, in the order of the table
+        let mut fused = Vec::new();
+        let mut new_idx = vec![0; ops.len() + 1];
+        let mut i = 0;
+        while i < ops.len() {
+            new_idx[i] = fused.len();
+            let matched = SUPERINSTRUCTIONS.iter().position(|seq| {
+                ops.get(i..i + seq.len()).is_some_and(|window| {
+                    window.iter().map(OpKind::of).eq(seq.iter().copied())
+                        && !is_target[i + 1..i + seq.len()].iter().any(|t| *t)
+                })
+            });
+            match matched {
+                Some(idx) => {
+                    let len = SUPERINSTRUCTIONS[idx].len();
+                    fused.push(SuperOp::fused(idx, &ops[i..i + len]));
+                    i += len;
+                }
+                None => {
+                    fused.push(SuperOp::Op(ops[i]));
+                    i += 1;
+                }
+            }
+        }
+        new_idx[ops.len()] = fused.len();
+
+        // Fix up jumps now that ops have moved
+        for op in &mut fused {
+            if let SuperOp::Op(Op::JmpZN(goto) | Op::Jmp(goto)) = op {
+                *goto = new_idx[*goto];
+            }
+        }
+
+        Frame {
+            code: fused,
+            max_locals: prog.max_locals,
+            max_stack: prog.max_stack,
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
+    }
+
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        let mut ip = 0;
+        stack.clear();
+        locals.clear();
+        stack.reserve(prog.max_stack);
+        locals.reserve(prog.max_locals);
+        loop {
+            let op = prog.code.get_unchecked(ip);
+            ip += 1;
+            if let Some(res) = op.step(&mut ip, args, stack, locals) {
+                break res;
+            }
+        }
+    }
+}
+
+// Generated by `cargo run --release --example superinstructions`
+superinstructions! {
+    GetLitrAddSetLocal: [Get, Litr, Add, SetLocal], // 60450 dispatches saved
+    GetArgAddSetLocal: [Get, Arg, Add, SetLocal], // 30000 dispatches saved
+    GetGetAddSetLocal: [Get, Get, Add, SetLocal], // 30000 dispatches saved
+    GetGetAddPushLocal: [Get, Get, Add, PushLocal], // 150 dispatches saved
+}
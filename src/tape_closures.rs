@@ -11,12 +11,12 @@ struct Tape<'a>(*const usize, PhantomData<&'a ()>);
 impl<'a> Tape<'a> {
     //unsafe fn next_fn(&mut self) -> OpFn { let res = std::mem::transmute(self.0.read()); self.0 = self.0.add(1); res }
     unsafe fn next_eval(&mut self, args: &[i64], locals: &mut Vec<i64>) -> i64 {
-        let f = std::mem::transmute::<_, OpFn>(self.0.read());
+        let f = std::mem::transmute::<usize, OpFn>(self.0.read());
         self.0 = self.0.add(1);
         f(args, self, locals)
     }
     unsafe fn next_int(&mut self) -> i64 {
-        let res = std::mem::transmute(self.0.read());
+        let res = self.0.read() as i64;
         self.0 = self.0.add(1);
         res
     }
@@ -31,7 +31,8 @@ impl<'a> Tape<'a> {
 }
 
 impl Vm for TapeClosures {
-    type Program<'a> = Vec<usize>;
+    type Program<'a> = Frame<Vec<usize>>;
+    type Context = Vec<i64>; // locals
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
         fn compile_inner(ops: &mut Vec<usize>, expr: &Expr) {
@@ -43,7 +44,7 @@ impl Vm for TapeClosures {
                     unsafe fn f(_: &[i64], tape: &mut Tape, _: &mut Vec<i64>) -> i64 {
                         tape.next_int()
                     }
-                    ops.push(unsafe { std::mem::transmute(f as OpFn) });
+                    ops.push(f as OpFn as usize);
                     ops.push(*x as usize);
                 }
                 Expr::Arg(idx) => {
@@ -51,15 +52,16 @@ impl Vm for TapeClosures {
                         let idx = tape.next_usize();
                         *args.get_unchecked(idx)
                     }
-                    ops.push(unsafe { std::mem::transmute(f as OpFn) });
+                    ops.push(f as OpFn as usize);
                     ops.push(*idx);
                 }
                 Expr::Get(local) => {
+                    #[allow(clippy::ptr_arg)] // Has to match `OpFn`
                     unsafe fn f(_: &[i64], tape: &mut Tape, locals: &mut Vec<i64>) -> i64 {
                         let local = tape.next_usize();
                         *locals.get_unchecked(locals.len() - local - 1)
                     }
-                    ops.push(unsafe { std::mem::transmute(f as OpFn) });
+                    ops.push(f as OpFn as usize);
                     ops.push(*local);
                 }
                 Expr::Add(x, y) => {
@@ -68,7 +70,7 @@ impl Vm for TapeClosures {
                         let y = tape.next_eval(args, locals);
                         x + y
                     }
-                    ops.push(unsafe { std::mem::transmute(f as OpFn) });
+                    ops.push(f as OpFn as usize);
                     compile_inner(ops, x);
                     compile_inner(ops, y);
                 }
@@ -80,7 +82,7 @@ impl Vm for TapeClosures {
                         locals.pop().unwrap_unchecked();
                         then
                     }
-                    ops.push(unsafe { std::mem::transmute(f as OpFn) });
+                    ops.push(f as OpFn as usize);
                     compile_inner(ops, rhs);
                     compile_inner(ops, then);
                 }
@@ -92,7 +94,7 @@ impl Vm for TapeClosures {
                         *locals.get_unchecked_mut(local_offs) = rhs;
                         UNIT
                     }
-                    ops.push(unsafe { std::mem::transmute(f as OpFn) });
+                    ops.push(f as OpFn as usize);
                     compile_inner(ops, rhs);
                     ops.push(*local);
                 }
@@ -107,7 +109,7 @@ impl Vm for TapeClosures {
                         tape.skip(end_skip);
                         UNIT
                     }
-                    ops.push(unsafe { std::mem::transmute(f as OpFn) });
+                    ops.push(f as OpFn as usize);
                     let end_fixup = ops.len();
                     ops.push(0);
                     compile_inner(ops, pred);
@@ -120,7 +122,7 @@ impl Vm for TapeClosures {
                         tape.next_eval(args, locals);
                         tape.next_eval(args, locals)
                     }
-                    ops.push(unsafe { std::mem::transmute(f as OpFn) });
+                    ops.push(f as OpFn as usize);
                     compile_inner(ops, a);
                     compile_inner(ops, b);
                 }
@@ -131,10 +133,24 @@ impl Vm for TapeClosures {
 
         compile_inner(&mut ops, expr);
 
-        ops
+        Frame {
+            code: ops,
+            max_locals: expr.max_locals(),
+            max_stack: 0, // Intermediate values live on the hardware stack
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        Vec::new()
     }
 
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
-        Tape(prog.as_ptr(), PhantomData).next_eval(args, &mut Vec::new())
+    unsafe fn execute_in(
+        locals: &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        locals.clear();
+        locals.reserve(prog.max_locals);
+        Tape(prog.code.as_ptr(), PhantomData).next_eval(args, locals)
     }
 }
@@ -1,4 +1,5 @@
 use super::*;
+use crate::rewrite::Rewrite;
 use std::marker::PhantomData;
 
 pub struct TapeContinuations;
@@ -6,6 +7,7 @@ pub struct TapeContinuations;
 #[derive(Default)]
 struct Reg {
     r0: i64, // Return value
+    #[allow(dead_code)]
     r1: i64, // Scratch register (currently unused)
 }
 
@@ -39,21 +41,21 @@ impl Stack {
 struct Tape<'a>(*const usize, PhantomData<&'a ()>);
 
 impl<'a> Tape<'a> {
-    unsafe fn this_eval(self, reg: Reg, args: *const i64, mut stack: Stack) {
-        let f = std::mem::transmute::<_, OpFn>(self.0.read());
+    unsafe fn this_eval(self, reg: Reg, args: *const i64, stack: Stack) {
+        let f = std::mem::transmute::<usize, OpFn>(self.0.read());
         f(reg, args, self, stack)
     }
     //unsafe fn next_fn(&mut self) -> OpFn { let res = std::mem::transmute(self.0.read()); self.0 = self.0.add(1); res }
     #[inline(always)]
-    unsafe fn next_eval(mut self, reg: Reg, args: *const i64, mut stack: Stack) {
+    unsafe fn next_eval(mut self, reg: Reg, args: *const i64, stack: Stack) {
         self.0 = self.0.add(1);
-        let f = std::mem::transmute::<_, OpFn>(self.0.read());
+        let f = std::mem::transmute::<usize, OpFn>(self.0.read());
         f(reg, args, self, stack)
     }
     #[inline(always)]
     unsafe fn next_int(&mut self) -> i64 {
         self.0 = self.0.add(1);
-        std::mem::transmute(self.0.read())
+        self.0.read() as i64
     }
     unsafe fn next_usize(&mut self) -> usize {
         self.0 = self.0.add(1);
@@ -68,7 +70,8 @@ impl<'a> Tape<'a> {
 }
 
 impl Vm for TapeContinuations {
-    type Program<'a> = Vec<usize>;
+    type Program<'a> = Frame<Vec<usize>>;
+    type Context = Vec<i64>; // stack
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
         enum Scope<'a> {
@@ -91,150 +94,66 @@ impl Vm for TapeContinuations {
         }
 
         fn compile_inner(ops: &mut Vec<usize>, expr: &Expr, scope: &Scope) {
-            fn returns(expr: &Expr) -> bool {
-                match expr {
-                    Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) | Expr::Add(_, _) => true,
-                    Expr::Let(_, expr) => returns(expr),
-                    Expr::Set(_, _) | Expr::While(_, _) => false,
-                    Expr::Then(_, b) => returns(b),
-                }
-            }
-
-            match expr {
-                Expr::Litr(x) => {
-                    unsafe fn litr(
-                        mut reg: Reg,
-                        args: *const i64,
-                        mut tape: Tape,
-                        mut stack: Stack,
-                    ) {
-                        let x = tape.next_int();
-                        reg.r0 = x;
-                        tape.next_eval(reg, args, stack);
-                    }
-                    ops.push(unsafe { std::mem::transmute(litr as OpFn) });
-                    ops.push(*x as usize);
-                }
-                Expr::Arg(idx) => {
-                    unsafe fn arg(
-                        mut reg: Reg,
-                        args: *const i64,
-                        mut tape: Tape,
-                        mut stack: Stack,
-                    ) {
-                        let idx = tape.next_usize();
-                        let x = args.add(idx).read();
-                        reg.r0 = x;
-                        tape.next_eval(reg, args, stack)
-                    }
-                    ops.push(unsafe { std::mem::transmute(arg as OpFn) });
-                    ops.push(*idx);
-                }
-                Expr::Get(local) => {
-                    unsafe fn get(
-                        mut reg: Reg,
-                        args: *const i64,
-                        mut tape: Tape,
-                        mut stack: Stack,
-                    ) {
-                        let local = tape.next_usize();
-                        let x = stack.get_offset(local);
-                        reg.r0 = x;
-                        tape.next_eval(reg, args, stack)
-                    }
-                    ops.push(unsafe { std::mem::transmute(get as OpFn) });
-                    ops.push(scope.local_offset_to_stack_offset(*local) + 1);
-                }
-                Expr::Add(x, y) => match &**y {
-                    Expr::Litr(1) => {
+            if let Some(rewrite) = Rewrite::find(expr) {
+                match rewrite {
+                    Rewrite::AddOne { x } => {
                         unsafe fn add_one(
                             mut reg: Reg,
                             args: *const i64,
                             tape: Tape,
-                            mut stack: Stack,
+                            stack: Stack,
                         ) {
                             reg.r0 += 1;
                             tape.next_eval(reg, args, stack)
                         }
                         compile_inner(ops, x, scope);
-                        ops.push(unsafe { std::mem::transmute(add_one as OpFn) });
+                        ops.push(add_one as OpFn as usize);
                     }
-                    Expr::Litr(y) => {
-                        unsafe fn add_litr(
+                    Rewrite::SubOne { x } => {
+                        unsafe fn sub_one(
                             mut reg: Reg,
                             args: *const i64,
-                            mut tape: Tape,
-                            mut stack: Stack,
+                            tape: Tape,
+                            stack: Stack,
                         ) {
-                            let y = tape.next_int();
-                            reg.r0 += y;
+                            reg.r0 -= 1;
                             tape.next_eval(reg, args, stack)
                         }
                         compile_inner(ops, x, scope);
-                        ops.push(unsafe { std::mem::transmute(add_litr as OpFn) });
-                        ops.push(*y as usize);
+                        ops.push(sub_one as OpFn as usize);
                     }
-                    Expr::Arg(1) => {
-                        unsafe fn add_arg1(
+                    Rewrite::AddLitr { x, y } => {
+                        unsafe fn add_litr(
                             mut reg: Reg,
                             args: *const i64,
                             mut tape: Tape,
-                            mut stack: Stack,
+                            stack: Stack,
                         ) {
-                            let y = args.add(1).read();
+                            let y = tape.next_int();
                             reg.r0 += y;
                             tape.next_eval(reg, args, stack)
                         }
                         compile_inner(ops, x, scope);
-                        ops.push(unsafe { std::mem::transmute(add_arg1 as OpFn) });
+                        ops.push(add_litr as OpFn as usize);
+                        ops.push(y as usize);
                     }
-                    _ => {
-                        unsafe fn add_swap(
-                            mut reg: Reg,
-                            args: *const i64,
-                            tape: Tape,
-                            mut stack: Stack,
-                        ) {
-                            stack.push(reg.r0);
-                            tape.next_eval(reg, args, stack)
-                        }
-                        unsafe fn add(
+                    Rewrite::AddArg1 { x } => {
+                        unsafe fn add_arg1(
                             mut reg: Reg,
                             args: *const i64,
                             tape: Tape,
-                            mut stack: Stack,
+                            stack: Stack,
                         ) {
-                            let y = stack.pop();
+                            let y = args.add(1).read();
                             reg.r0 += y;
                             tape.next_eval(reg, args, stack)
                         }
                         compile_inner(ops, x, scope);
-                        ops.push(unsafe { std::mem::transmute(add_swap as OpFn) });
-                        compile_inner(ops, y, &Scope::Intermediate(scope));
-                        ops.push(unsafe { std::mem::transmute(add as OpFn) });
+                        ops.push(add_arg1 as OpFn as usize);
                     }
-                },
-                Expr::Let(rhs, then) => {
-                    compile_inner(ops, rhs, scope);
-                    unsafe fn let_push(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
-                        stack.push(reg.r0);
-                        tape.next_eval(reg, args, stack)
-                    }
-                    ops.push(unsafe { std::mem::transmute(let_push as OpFn) });
-                    compile_inner(ops, then, &Scope::Local(scope));
-                    unsafe fn let_pop(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
-                        stack.pop();
-                        tape.next_eval(reg, args, stack)
-                    }
-                    ops.push(unsafe { std::mem::transmute(let_pop as OpFn) });
-                }
-                Expr::Set(local, rhs) => {
-                    if let Expr::Add(a, b) = &**rhs
-                        && let Expr::Get(y) = &**a
-                        && local == y
-                    {
-                        compile_inner(ops, b, scope);
-                        let local_offset = scope.local_offset_to_stack_offset(*local);
+                    Rewrite::AddAssign { local, y } => {
+                        compile_inner(ops, y, scope);
+                        let local_offset = scope.local_offset_to_stack_offset(local);
                         unsafe fn add_assign_at<const N: usize>(
                             reg: Reg,
                             args: *const i64,
@@ -247,12 +166,8 @@ impl Vm for TapeContinuations {
                             tape.next_eval(reg, args, stack)
                         }
                         match local_offset {
-                            0 => {
-                                ops.push(unsafe { std::mem::transmute(add_assign_at::<0> as OpFn) })
-                            }
-                            1 => {
-                                ops.push(unsafe { std::mem::transmute(add_assign_at::<1> as OpFn) })
-                            }
+                            0 => ops.push(add_assign_at::<0> as OpFn as usize),
+                            1 => ops.push(add_assign_at::<1> as OpFn as usize),
                             _ => {
                                 unsafe fn add_assign(
                                     reg: Reg,
@@ -267,38 +182,91 @@ impl Vm for TapeContinuations {
                                     stack.set_offset(local, a + b);
                                     tape.next_eval(reg, args, stack)
                                 }
-                                ops.push(unsafe { std::mem::transmute(add_assign as OpFn) });
+                                ops.push(add_assign as OpFn as usize);
                                 ops.push(local_offset + 1);
                             }
                         }
-                    } else {
-                        unsafe fn set(
-                            reg: Reg,
-                            args: *const i64,
-                            mut tape: Tape,
-                            mut stack: Stack,
-                        ) {
-                            let local = tape.next_usize();
-                            let x = reg.r0;
-                            stack.set_offset(local, x);
-                            tape.next_eval(reg, args, stack)
-                        }
-                        compile_inner(ops, rhs, scope);
-                        ops.push(unsafe { std::mem::transmute(set as OpFn) });
-                        ops.push(scope.local_offset_to_stack_offset(*local) + 1);
                     }
                 }
+                return;
+            }
+
+            match expr {
+                Expr::Litr(x) => {
+                    unsafe fn litr(mut reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+                        let x = tape.next_int();
+                        reg.r0 = x;
+                        tape.next_eval(reg, args, stack);
+                    }
+                    ops.push(litr as OpFn as usize);
+                    ops.push(*x as usize);
+                }
+                Expr::Arg(idx) => {
+                    unsafe fn arg(mut reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+                        let idx = tape.next_usize();
+                        let x = args.add(idx).read();
+                        reg.r0 = x;
+                        tape.next_eval(reg, args, stack)
+                    }
+                    ops.push(arg as OpFn as usize);
+                    ops.push(*idx);
+                }
+                Expr::Get(local) => {
+                    unsafe fn get(mut reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+                        let local = tape.next_usize();
+                        let x = stack.get_offset(local);
+                        reg.r0 = x;
+                        tape.next_eval(reg, args, stack)
+                    }
+                    ops.push(get as OpFn as usize);
+                    ops.push(scope.local_offset_to_stack_offset(*local) + 1);
+                }
+                Expr::Add(x, y) => {
+                    unsafe fn add_swap(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+                        stack.push(reg.r0);
+                        tape.next_eval(reg, args, stack)
+                    }
+                    unsafe fn add(mut reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+                        let y = stack.pop();
+                        reg.r0 += y;
+                        tape.next_eval(reg, args, stack)
+                    }
+                    compile_inner(ops, x, scope);
+                    ops.push(add_swap as OpFn as usize);
+                    compile_inner(ops, y, &Scope::Intermediate(scope));
+                    ops.push(add as OpFn as usize);
+                }
+                Expr::Let(rhs, then) => {
+                    compile_inner(ops, rhs, scope);
+                    unsafe fn let_push(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+                        stack.push(reg.r0);
+                        tape.next_eval(reg, args, stack)
+                    }
+                    ops.push(let_push as OpFn as usize);
+                    compile_inner(ops, then, &Scope::Local(scope));
+                    unsafe fn let_pop(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+                        stack.pop();
+                        tape.next_eval(reg, args, stack)
+                    }
+                    ops.push(let_pop as OpFn as usize);
+                }
+                Expr::Set(local, rhs) => {
+                    unsafe fn set(reg: Reg, args: *const i64, mut tape: Tape, mut stack: Stack) {
+                        let local = tape.next_usize();
+                        let x = reg.r0;
+                        stack.set_offset(local, x);
+                        tape.next_eval(reg, args, stack)
+                    }
+                    compile_inner(ops, rhs, scope);
+                    ops.push(set as OpFn as usize);
+                    ops.push(scope.local_offset_to_stack_offset(*local) + 1);
+                }
                 Expr::While(pred, body) => {
                     // Pred
                     let start = ops.len();
                     compile_inner(ops, pred, scope);
                     // Check
-                    unsafe fn while_pred(
-                        reg: Reg,
-                        args: *const i64,
-                        mut tape: Tape,
-                        mut stack: Stack,
-                    ) {
+                    unsafe fn while_pred(reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
                         let end_skip = tape.next_usize();
                         let pred = reg.r0;
                         if pred <= 0 {
@@ -306,24 +274,19 @@ impl Vm for TapeContinuations {
                         }
                         tape.next_eval(reg, args, stack)
                     }
-                    ops.push(unsafe { std::mem::transmute(while_pred as OpFn) });
+                    ops.push(while_pred as OpFn as usize);
                     let end_fixup = ops.len();
                     ops.push(0);
                     let body_start = ops.len();
                     // Body
                     compile_inner(ops, body, scope);
                     // Loop
-                    unsafe fn while_loop(
-                        reg: Reg,
-                        args: *const i64,
-                        mut tape: Tape,
-                        mut stack: Stack,
-                    ) {
+                    unsafe fn while_loop(reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
                         let unskip = tape.next_usize();
                         tape.unskip(unskip);
                         tape.next_eval(reg, args, stack)
                     }
-                    ops.push(unsafe { std::mem::transmute(while_loop as OpFn) });
+                    ops.push(while_loop as OpFn as usize);
                     ops.push(ops.len() - start + 1);
                     // Fixup
                     ops[end_fixup] = ops.len() - body_start;
@@ -337,18 +300,71 @@ impl Vm for TapeContinuations {
 
         let mut ops = Vec::new();
 
+        // Locals and intermediate values share the stack, so its depth depends on how each node gets compiled above
+        fn stack_depth(expr: &Expr) -> usize {
+            match Rewrite::find(expr) {
+                Some(
+                    Rewrite::AddOne { x }
+                    | Rewrite::SubOne { x }
+                    | Rewrite::AddLitr { x, .. }
+                    | Rewrite::AddArg1 { x },
+                ) => return stack_depth(x),
+                Some(Rewrite::AddAssign { y, .. }) => return stack_depth(y),
+                None => {}
+            }
+            match expr {
+                Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => 0,
+                Expr::Add(x, y) => stack_depth(x).max(1 + stack_depth(y)),
+                Expr::Let(rhs, then) => stack_depth(rhs).max(1 + stack_depth(then)),
+                Expr::Set(_, rhs) => stack_depth(rhs),
+                Expr::While(x, y) | Expr::Then(x, y) => stack_depth(x).max(stack_depth(y)),
+            }
+        }
+
         compile_inner(&mut ops, expr, &Scope::None);
 
-        unsafe fn ret(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {}
-        ops.push(unsafe { std::mem::transmute(ret as OpFn) });
+        unsafe fn ret(reg: Reg, _args: *const i64, _tape: Tape, mut stack: Stack) {
+            // The stack is balanced by now, so the result goes in the bottom slot
+            stack.push(reg.r0);
+        }
+        ops.push(ret as OpFn as usize);
+
+        Frame {
+            code: ops,
+            max_locals: expr.max_locals(),
+            // Leave room for the result
+            max_stack: stack_depth(expr).max(1),
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        Vec::new()
+    }
 
-        ops
+    unsafe fn execute_in(stack: &mut Self::Context, prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+        reserve_slots(stack, prog.max_stack);
+        Tape(prog.code.as_ptr(), PhantomData).this_eval(
+            Reg::default(),
+            args.as_ptr(),
+            Stack(stack.as_mut_ptr()),
+        );
+        stack[0]
     }
 
-    unsafe fn execute(prog: &Self::Program<'_>, args: &[i64]) -> i64 {
-        let stack_raw = Box::into_raw(vec![0i64; 1024].into_boxed_slice());
-        let mut stack = Stack(stack_raw as _);
-        Tape(prog.as_ptr(), PhantomData).this_eval(Reg::default(), args.as_ptr(), stack);
-        (*stack_raw)[0]
+    unsafe fn execute_batch_in(
+        buf: &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[&[i64]],
+        out: &mut [i64],
+    ) {
+        assert_eq!(args.len(), out.len());
+        // The stack and tape entry are the same for every execution, so only set them up once
+        reserve_slots(buf, prog.max_stack);
+        let stack = Stack(buf.as_mut_ptr());
+        let tape = Tape(prog.code.as_ptr(), PhantomData);
+        for (args, out) in args.iter().zip(out) {
+            tape.this_eval(Reg::default(), args.as_ptr(), Stack(stack.0));
+            *out = stack.0.read();
+        }
     }
 }
@@ -0,0 +1,319 @@
+use super::*;
+
+// `TapeContinuations`, except each op passes control to the next with `become` rather than an ordinary call in tail
+// position. Tail calls are then guaranteed rather than left to the optimiser, so loops run in constant native stack
+// space even in debug builds.
+pub struct TapeTailCalls;
+
+#[derive(Default)]
+struct Reg {
+    r0: i64, // Return value
+}
+
+type OpFn = unsafe fn(Reg, *const i64, Tape, Stack);
+
+struct Stack(*mut i64);
+
+impl Stack {
+    #[inline(always)]
+    unsafe fn push(&mut self, x: i64) {
+        self.0.write(x);
+        self.0 = self.0.add(1);
+    }
+
+    #[inline(always)]
+    unsafe fn pop(&mut self) -> i64 {
+        self.0 = self.0.sub(1);
+        self.0.read()
+    }
+
+    #[inline(always)]
+    unsafe fn set_offset(&mut self, offset: usize, x: i64) {
+        self.0.sub(offset).write(x);
+    }
+
+    #[inline(always)]
+    unsafe fn get_offset(&self, offset: usize) -> i64 {
+        self.0.sub(offset).read()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Tape(*const usize);
+
+impl Tape {
+    #[inline(always)]
+    unsafe fn next_int(&mut self) -> i64 {
+        self.0 = self.0.add(1);
+        self.0.read() as i64
+    }
+
+    #[inline(always)]
+    unsafe fn next_usize(&mut self) -> usize {
+        self.0 = self.0.add(1);
+        self.0.read()
+    }
+}
+
+// Tail-call the next op on the tape. This has to be a macro, since `become` only applies to the function it's written in.
+macro_rules! next {
+    ($reg:expr, $args:expr, $tape:expr, $stack:expr) => {{
+        let mut tape: Tape = $tape;
+        tape.0 = tape.0.add(1);
+        let f = std::mem::transmute::<usize, OpFn>(tape.0.read());
+        become f($reg, $args, tape, $stack)
+    }};
+}
+
+unsafe fn litr(mut reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+    reg.r0 = tape.next_int();
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn arg(mut reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+    let idx = tape.next_usize();
+    reg.r0 = args.add(idx).read();
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn get(mut reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+    let local = tape.next_usize();
+    reg.r0 = stack.get_offset(local);
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn add_one(mut reg: Reg, args: *const i64, tape: Tape, stack: Stack) {
+    reg.r0 += 1;
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn add_litr(mut reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+    reg.r0 += tape.next_int();
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn add_arg1(mut reg: Reg, args: *const i64, tape: Tape, stack: Stack) {
+    reg.r0 += args.add(1).read();
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn add_swap(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+    stack.push(reg.r0);
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn add(mut reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+    reg.r0 += stack.pop();
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn let_push(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+    stack.push(reg.r0);
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn let_pop(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+    stack.pop();
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn add_assign_at<const N: usize>(reg: Reg, args: *const i64, tape: Tape, mut stack: Stack) {
+    let a = stack.get_offset(N + 1);
+    stack.set_offset(N + 1, a + reg.r0);
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn add_assign(reg: Reg, args: *const i64, mut tape: Tape, mut stack: Stack) {
+    let local = tape.next_usize();
+    let a = stack.get_offset(local);
+    stack.set_offset(local, a + reg.r0);
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn set(reg: Reg, args: *const i64, mut tape: Tape, mut stack: Stack) {
+    let local = tape.next_usize();
+    stack.set_offset(local, reg.r0);
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn while_pred(reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+    let end_skip = tape.next_usize();
+    if reg.r0 <= 0 {
+        tape.0 = tape.0.add(end_skip);
+    }
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn while_loop(reg: Reg, args: *const i64, mut tape: Tape, stack: Stack) {
+    let unskip = tape.next_usize();
+    tape.0 = tape.0.sub(unskip);
+    next!(reg, args, tape, stack)
+}
+
+unsafe fn ret(reg: Reg, _args: *const i64, _tape: Tape, mut stack: Stack) {
+    // The stack is balanced by now, so the result goes in the bottom slot
+    stack.push(reg.r0);
+}
+
+impl Vm for TapeTailCalls {
+    type Program<'a> = Frame<Vec<usize>>;
+    type Context = Vec<i64>; // stack
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        // Offsets of locals on the stack, which is shared with intermediate values
+        enum Scope<'a> {
+            None,
+            Intermediate(&'a Self),
+            Local(&'a Self),
+        }
+
+        impl<'a> Scope<'a> {
+            fn local_offset_to_stack_offset(&self, offset: usize) -> usize {
+                match self {
+                    Self::None => unreachable!("local not in stack"),
+                    Self::Intermediate(parent) => parent.local_offset_to_stack_offset(offset) + 1,
+                    Self::Local(_) if offset == 0 => 0,
+                    Self::Local(parent) => parent.local_offset_to_stack_offset(offset - 1) + 1,
+                }
+            }
+        }
+
+        fn has_set(expr: &Expr) -> bool {
+            match expr {
+                Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => false,
+                Expr::Set(_, _) => true,
+                Expr::Add(x, y) | Expr::Let(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+                    has_set(x) || has_set(y)
+                }
+            }
+        }
+
+        // `local = local + b` can add `b` to the local in place, unless `b` changes the local itself
+        fn add_assign_rhs<'a>(local: &LocalOffset, rhs: &'a Expr) -> Option<&'a Expr> {
+            match rhs {
+                Expr::Add(a, b) if matches!(&**a, Expr::Get(y) if y == local) && !has_set(b) => {
+                    Some(b)
+                }
+                _ => None,
+            }
+        }
+
+        fn push_op(ops: &mut Vec<usize>, f: OpFn) {
+            ops.push(f as usize);
+        }
+
+        fn compile_inner(ops: &mut Vec<usize>, expr: &Expr, scope: &Scope) {
+            match expr {
+                Expr::Litr(x) => {
+                    push_op(ops, litr);
+                    ops.push(*x as usize);
+                }
+                Expr::Arg(idx) => {
+                    push_op(ops, arg);
+                    ops.push(*idx);
+                }
+                Expr::Get(local) => {
+                    push_op(ops, get);
+                    ops.push(scope.local_offset_to_stack_offset(*local) + 1);
+                }
+                Expr::Add(x, y) => {
+                    compile_inner(ops, x, scope);
+                    match &**y {
+                        Expr::Litr(1) => push_op(ops, add_one),
+                        Expr::Litr(y) => {
+                            push_op(ops, add_litr);
+                            ops.push(*y as usize);
+                        }
+                        Expr::Arg(1) => push_op(ops, add_arg1),
+                        _ => {
+                            push_op(ops, add_swap);
+                            compile_inner(ops, y, &Scope::Intermediate(scope));
+                            push_op(ops, add);
+                        }
+                    }
+                }
+                Expr::Let(rhs, then) => {
+                    compile_inner(ops, rhs, scope);
+                    push_op(ops, let_push);
+                    compile_inner(ops, then, &Scope::Local(scope));
+                    push_op(ops, let_pop);
+                }
+                Expr::Set(local, rhs) => match add_assign_rhs(local, rhs) {
+                    Some(b) => {
+                        compile_inner(ops, b, scope);
+                        match scope.local_offset_to_stack_offset(*local) {
+                            0 => push_op(ops, add_assign_at::<0>),
+                            1 => push_op(ops, add_assign_at::<1>),
+                            offset => {
+                                push_op(ops, add_assign);
+                                ops.push(offset + 1);
+                            }
+                        }
+                    }
+                    None => {
+                        compile_inner(ops, rhs, scope);
+                        push_op(ops, set);
+                        ops.push(scope.local_offset_to_stack_offset(*local) + 1);
+                    }
+                },
+                Expr::While(pred, body) => {
+                    let start = ops.len();
+                    compile_inner(ops, pred, scope);
+                    push_op(ops, while_pred);
+                    let end_fixup = ops.len();
+                    ops.push(0); // Will be fixed up
+                    let body_start = ops.len();
+                    compile_inner(ops, body, scope);
+                    push_op(ops, while_loop);
+                    ops.push(ops.len() - start + 1);
+                    ops[end_fixup] = ops.len() - body_start;
+                }
+                Expr::Then(a, b) => {
+                    compile_inner(ops, a, scope);
+                    compile_inner(ops, b, scope);
+                }
+            }
+        }
+
+        // Locals and intermediate values share the stack, so its depth depends on how each node gets compiled above
+        fn stack_depth(expr: &Expr) -> usize {
+            match expr {
+                Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => 0,
+                Expr::Add(x, y) => match &**y {
+                    Expr::Litr(_) | Expr::Arg(1) => stack_depth(x),
+                    _ => stack_depth(x).max(1 + stack_depth(y)),
+                },
+                Expr::Let(rhs, then) => stack_depth(rhs).max(1 + stack_depth(then)),
+                Expr::Set(local, rhs) => stack_depth(add_assign_rhs(local, rhs).unwrap_or(rhs)),
+                Expr::While(x, y) | Expr::Then(x, y) => stack_depth(x).max(stack_depth(y)),
+            }
+        }
+
+        let mut ops = Vec::new();
+        compile_inner(&mut ops, expr, &Scope::None);
+        push_op(&mut ops, ret);
+
+        Frame {
+            code: ops,
+            max_locals: expr.max_locals(),
+            // Leave room for the result
+            max_stack: stack_depth(expr).max(1),
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        Vec::new()
+    }
+
+    unsafe fn execute_in(stack: &mut Self::Context, prog: &Self::Program<'_>, args: &[i64]) -> i64 {
+        reserve_slots(stack, prog.max_stack);
+        let f = std::mem::transmute::<usize, OpFn>(prog.code[0]);
+        f(
+            Reg::default(),
+            args.as_ptr(),
+            Tape(prog.code.as_ptr()),
+            Stack(stack.as_mut_ptr()),
+        );
+        stack[0]
+    }
+}
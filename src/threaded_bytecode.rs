@@ -0,0 +1,308 @@
+use super::*;
+use crate::bytecode::Op;
+
+// Direct-threaded versions of `Bytecode`. The ops produced by `Bytecode::compile` are translated into a stream of
+// handler addresses, each followed by its operands (if any), so that dispatch never needs to `match` on an op.
+//
+// `ThreadedBytecode` calls each handler from a single dispatch loop. `ReplicatedBytecode` instead has every handler
+// dispatch the next one itself, giving each handler its own indirect branch for the branch predictor to learn from.
+// Like `TapeTailCalls`, the next handler is called with `become`, so the hardware stack doesn't grow with every op, even
+// in debug builds.
+pub struct ThreadedBytecode;
+pub struct ReplicatedBytecode;
+
+struct Regs {
+    ip: *const usize,
+    sp: *mut i64, // One past the top of the stack
+    lp: *mut i64, // One past the top of the locals
+    args: *const i64,
+}
+
+impl Regs {
+    #[inline(always)]
+    unsafe fn operand(&mut self) -> usize {
+        let x = self.ip.read();
+        self.ip = self.ip.add(1);
+        x
+    }
+
+    #[inline(always)]
+    unsafe fn push(&mut self, x: i64) {
+        self.sp.write(x);
+        self.sp = self.sp.add(1);
+    }
+
+    #[inline(always)]
+    unsafe fn pop(&mut self) -> i64 {
+        self.sp = self.sp.sub(1);
+        self.sp.read()
+    }
+
+    // Jump targets are stored relative to the end of the operand
+    #[inline(always)]
+    unsafe fn jump(&mut self) {
+        let offset = self.operand() as isize;
+        self.ip = self.ip.offset(offset);
+    }
+}
+
+// The work done by an op. Returns `true` if execution should stop.
+trait Handler {
+    unsafe fn run(r: &mut Regs) -> bool;
+}
+
+struct Litr;
+struct Arg;
+struct Get;
+struct Add;
+struct PushLocal;
+struct PopLocal;
+struct SetLocal;
+struct Pop;
+struct JmpZN;
+struct Jmp;
+struct Ret;
+
+impl Handler for Litr {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        let x = r.operand() as i64;
+        r.push(x);
+        false
+    }
+}
+
+impl Handler for Arg {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        let idx = r.operand();
+        r.push(r.args.add(idx).read());
+        false
+    }
+}
+
+impl Handler for Get {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        let local = r.operand();
+        r.push(r.lp.sub(local + 1).read());
+        false
+    }
+}
+
+impl Handler for Add {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        let x = r.pop();
+        let y = r.pop();
+        r.push(x + y);
+        false
+    }
+}
+
+impl Handler for PushLocal {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        let x = r.pop();
+        r.lp.write(x);
+        r.lp = r.lp.add(1);
+        false
+    }
+}
+
+impl Handler for PopLocal {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        r.lp = r.lp.sub(1);
+        false
+    }
+}
+
+impl Handler for SetLocal {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        let local = r.operand();
+        let x = r.pop();
+        r.lp.sub(local + 1).write(x);
+        false
+    }
+}
+
+impl Handler for Pop {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        r.pop();
+        false
+    }
+}
+
+impl Handler for JmpZN {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        if r.pop() <= 0 {
+            r.jump();
+        } else {
+            r.ip = r.ip.add(1);
+        }
+        false
+    }
+}
+
+impl Handler for Jmp {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        r.jump();
+        false
+    }
+}
+
+impl Handler for Ret {
+    #[inline(always)]
+    unsafe fn run(r: &mut Regs) -> bool {
+        // The stack is balanced by now, so the result goes in the bottom slot
+        let x = r.pop();
+        r.sp.write(x);
+        true
+    }
+}
+
+type LoopFn = unsafe fn(&mut Regs) -> bool;
+
+unsafe fn looped<H: Handler>(r: &mut Regs) -> bool {
+    H::run(r)
+}
+
+type ReplicatedFn = unsafe fn(*const usize, *mut i64, *mut i64, *const i64);
+
+unsafe fn replicated<H: Handler>(ip: *const usize, sp: *mut i64, lp: *mut i64, args: *const i64) {
+    let mut r = Regs { ip, sp, lp, args };
+    if H::run(&mut r) {
+        return;
+    }
+    let next = std::mem::transmute::<usize, ReplicatedFn>(r.operand());
+    become next(r.ip, r.sp, r.lp, r.args)
+}
+
+// Picks the address of the handler for each op, in either dispatch style
+trait Threading {
+    fn handler<H: Handler>() -> usize;
+}
+
+impl Threading for ThreadedBytecode {
+    fn handler<H: Handler>() -> usize {
+        looped::<H> as LoopFn as usize
+    }
+}
+
+impl Threading for ReplicatedBytecode {
+    fn handler<H: Handler>() -> usize {
+        replicated::<H> as ReplicatedFn as usize
+    }
+}
+
+fn thread<T: Threading>(expr: &Expr) -> Frame<Vec<usize>> {
+    let ops = Bytecode::compile(expr);
+
+    // Ops have variable width once threaded, so jumps get fixed up once every op has a position
+    let mut code = Vec::new();
+    let mut positions = Vec::with_capacity(ops.code.len());
+    let mut fixups = Vec::new();
+    for op in &ops.code {
+        positions.push(code.len());
+        match op {
+            Op::Litr(x) => code.extend([T::handler::<Litr>(), *x as usize]),
+            Op::Arg(idx) => code.extend([T::handler::<Arg>(), *idx]),
+            Op::Get(local) => code.extend([T::handler::<Get>(), *local]),
+            Op::Add => code.push(T::handler::<Add>()),
+            Op::PushLocal => code.push(T::handler::<PushLocal>()),
+            Op::PopLocal => code.push(T::handler::<PopLocal>()),
+            Op::SetLocal(local) => code.extend([T::handler::<SetLocal>(), *local]),
+            Op::Pop => code.push(T::handler::<Pop>()),
+            Op::JmpZN(goto) => {
+                code.push(T::handler::<JmpZN>());
+                fixups.push((code.len(), *goto));
+                code.push(0); // Will be fixed up
+            }
+            Op::Jmp(goto) => {
+                code.push(T::handler::<Jmp>());
+                fixups.push((code.len(), *goto));
+                code.push(0); // Will be fixed up
+            }
+            Op::Ret => code.push(T::handler::<Ret>()),
+        }
+    }
+    for (at, goto) in fixups {
+        code[at] = (positions[goto] as isize - (at as isize + 1)) as usize;
+    }
+
+    Frame {
+        code,
+        max_locals: ops.max_locals,
+        max_stack: ops.max_stack,
+    }
+}
+
+impl Vm for ThreadedBytecode {
+    type Program<'a> = Frame<Vec<usize>>;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        thread::<Self>(expr)
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
+    }
+
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        reserve_slots(stack, prog.max_stack);
+        reserve_slots(locals, prog.max_locals);
+        let mut r = Regs {
+            ip: prog.code.as_ptr(),
+            sp: stack.as_mut_ptr(),
+            lp: locals.as_mut_ptr(),
+            args: args.as_ptr(),
+        };
+        loop {
+            let f = std::mem::transmute::<usize, LoopFn>(r.operand());
+            if f(&mut r) {
+                break stack[0];
+            }
+        }
+    }
+}
+
+impl Vm for ReplicatedBytecode {
+    type Program<'a> = Frame<Vec<usize>>;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        thread::<Self>(expr)
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
+    }
+
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        reserve_slots(stack, prog.max_stack);
+        reserve_slots(locals, prog.max_locals);
+        let ip = prog.code.as_ptr();
+        let f = std::mem::transmute::<usize, ReplicatedFn>(ip.read());
+        f(
+            ip.add(1),
+            stack.as_mut_ptr(),
+            locals.as_mut_ptr(),
+            args.as_ptr(),
+        );
+        stack[0]
+    }
+}
@@ -0,0 +1,124 @@
+use super::*;
+use core::marker::PhantomData;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc, Arc, OnceLock,
+    },
+};
+
+// Tiered execution: programs start out on `Lo`, which should be cheap to compile, and count their executions. Once a
+// program has been executed `THRESHOLD` times, it gets recompiled with `Hi` on a background thread, and executions switch
+// over to `Hi` as soon as that's done. Programs that are only executed a few times never pay for compiling with `Hi`.
+//
+// Programs keep their own copy of the `Expr`, which `Lo`'s program borrows from and the background thread compiles `Hi`'s
+// program from, so they don't borrow from the caller's.
+pub struct Tiered<Lo, Hi, const THRESHOLD: u32>(PhantomData<fn() -> (Lo, Hi)>);
+
+impl<Lo, Hi, const THRESHOLD: u32> Tiered<Lo, Hi, THRESHOLD> {
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Lo, Hi, const THRESHOLD: u32> Default for Tiered<Lo, Hi, THRESHOLD> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct TieredProgram<Lo: Vm, P> {
+    // Borrows from `expr`, so it's declared (and dropped) first
+    cold: Lo::Program<'static>,
+    expr: Arc<Expr>,
+    executions: AtomicU32,
+    // Filled in by the background thread
+    hot: Arc<OnceLock<P>>,
+}
+
+impl<Lo: Vm, P: Send + Sync + 'static> TieredProgram<Lo, P> {
+    /// Whether executions have switched over to the faster backend.
+    pub fn is_promoted(&self) -> bool {
+        self.hot.get().is_some()
+    }
+
+    fn promote(&self, compile: fn(&Expr) -> P) {
+        let expr = self.expr.clone();
+        let hot = self.hot.clone();
+        // If the compiler thread has gone, the program just stays on `Lo`
+        in_background(Box::new(move || {
+            let _ = hot.set(compile(&expr));
+        }));
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Run a job on the compiler thread, which is shared by every program so that promoting one doesn't pay for spawning a
+// thread. A job that panics leaves its program on `Lo`, without taking the thread down with it.
+fn in_background(job: Job) {
+    static JOBS: OnceLock<mpsc::Sender<Job>> = OnceLock::new();
+    let jobs = JOBS.get_or_init(|| {
+        let (send, recv) = mpsc::channel::<Job>();
+        std::thread::spawn(move || {
+            for job in recv {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+        });
+        send
+    });
+    let _ = jobs.send(job);
+}
+
+impl<Lo, Hi, P, const THRESHOLD: u32> Vm for Tiered<Lo, Hi, THRESHOLD>
+where
+    Lo: Vm,
+    Hi: for<'a> Vm<Program<'a> = P>,
+    P: Send + Sync + 'static,
+{
+    type Program<'a> = TieredProgram<Lo, P>;
+    type Context = (Lo::Context, Hi::Context);
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        const {
+            assert!(
+                THRESHOLD > 0,
+                "programs have to be executed at least once to be promoted"
+            )
+        };
+        let expr = Arc::new(expr.clone());
+        // SAFETY: The `Expr` is never moved or modified once it's in the `Arc`, and `cold` is dropped before `expr`
+        let cold = Lo::compile(unsafe { &*Arc::as_ptr(&expr) });
+        TieredProgram {
+            cold,
+            expr,
+            executions: AtomicU32::new(0),
+            hot: Arc::new(OnceLock::new()),
+        }
+    }
+
+    fn new_context() -> Self::Context {
+        (Lo::new_context(), Hi::new_context())
+    }
+
+    unsafe fn execute_in(
+        (lo, hi): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        if let Some(hot) = prog.hot.get() {
+            return Hi::execute_in(hi, hot, args);
+        }
+        // Counting stops at `THRESHOLD`, so exactly one execution starts the promotion, even if it never finishes
+        let counted = prog
+            .executions
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < THRESHOLD).then_some(n + 1)
+            });
+        if counted == Ok(THRESHOLD - 1) {
+            prog.promote(|expr| Hi::compile(expr));
+        }
+        Lo::execute_in(lo, &prog.cold, args)
+    }
+}
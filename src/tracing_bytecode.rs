@@ -0,0 +1,383 @@
+use super::*;
+use crate::{
+    bytecode::Op,
+    closure_continuations::{make_func, Func},
+};
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    OnceLock,
+};
+
+// A tracing JIT on top of `Bytecode`. Loop back-edges (`Op::Jmp`) are counted, and once a loop is hot, the interpreter
+// records the ops executed by one iteration of it. Since the trace is straight-line code, its stack traffic can be
+// resolved at compile time: values become expression trees, leaving only stores to locals and guards on the branches
+// taken while recording. These compile to a chain of closures in the style of `ClosureContinuations`, which then runs the
+// loop until a guard fails, at which point the interpreter picks up where the trace left off.
+//
+// Only innermost loops are traced: a trace that runs into another loop's back-edge is abandoned.
+pub struct TracingBytecode;
+
+// How many back-edges a loop takes before it gets traced
+const HOT: u32 = 16;
+// Traces longer than this (in ops) are abandoned
+const MAX_TRACE: usize = 1024;
+
+pub struct TracingProgram {
+    bytecode: Frame<Vec<Op>>,
+    // Indexed by the `ip` of each loop's header, the target of its back-edge. Other entries are unused.
+    loops: Vec<Loop>,
+}
+
+#[derive(Default)]
+struct Loop {
+    hits: AtomicU32,
+    // `None` if the loop can't be traced
+    trace: OnceLock<Option<Trace>>,
+}
+
+struct Trace {
+    // Returns the index of the side exit taken, or `LOOP` to run another iteration
+    code: Func<'static>,
+    exits: Vec<SideExit>,
+    // Temporaries live in the locals buffer, above every local
+    max_slots: usize,
+}
+
+const LOOP: i64 = -1;
+
+// Where to resume the interpreter when a guard fails
+struct SideExit {
+    ip: usize,
+    locals: usize,
+    // Values that the interpreter expects on the stack, bottom first
+    stack: Vec<Val>,
+}
+
+// A value computed by the trace, with locals (and temporaries) addressed from the bottom of the locals buffer
+#[derive(Clone, Debug)]
+enum Val {
+    Litr(i64),
+    Arg(usize),
+    Slot(usize),
+    Add(Box<Val>, Box<Val>),
+}
+
+type ValFn = Box<dyn Fn(*const i64, *mut i64) -> i64 + Send + Sync>;
+
+impl Val {
+    fn reads(&self, slot: usize) -> bool {
+        match self {
+            Val::Litr(_) | Val::Arg(_) => false,
+            Val::Slot(s) => *s == slot,
+            Val::Add(x, y) => x.reads(slot) || y.reads(slot),
+        }
+    }
+
+    unsafe fn eval(&self, args: *const i64, slots: *mut i64) -> i64 {
+        match self {
+            Val::Litr(x) => *x,
+            Val::Arg(idx) => *args.add(*idx),
+            Val::Slot(slot) => *slots.add(*slot),
+            Val::Add(x, y) => x.eval(args, slots) + y.eval(args, slots),
+        }
+    }
+
+    fn compile(&self) -> ValFn {
+        match self.clone() {
+            Val::Litr(x) => Box::new(move |_, _| x),
+            Val::Arg(idx) => Box::new(move |args, _| unsafe { *args.add(idx) }),
+            Val::Slot(slot) => Box::new(move |_, slots| unsafe { *slots.add(slot) }),
+            Val::Add(x, y) => {
+                let (x, y) = (x.compile(), y.compile());
+                Box::new(move |args, slots| x(args, slots) + y(args, slots))
+            }
+        }
+    }
+}
+
+// Bind `$v` to a closure computing `$val` and evaluate `$body`, once for each of the most common shapes of value so that
+// `$body` can inline them, and once for everything else
+macro_rules! with_val {
+    ($val:expr, |$v:ident| $body:expr) => {
+        match $val {
+            Val::Litr(x) => {
+                let x = *x;
+                let $v = move |_: *const i64, _: *mut i64| x;
+                $body
+            }
+            Val::Slot(slot) => {
+                let slot = *slot;
+                let $v = move |_: *const i64, slots: *mut i64| unsafe { *slots.add(slot) };
+                $body
+            }
+            Val::Add(x, y) => match (&**x, &**y) {
+                (Val::Slot(slot), Val::Litr(y)) => {
+                    let (slot, y) = (*slot, *y);
+                    let $v = move |_: *const i64, slots: *mut i64| unsafe { *slots.add(slot) } + y;
+                    $body
+                }
+                (Val::Slot(slot), Val::Arg(idx)) => {
+                    let (slot, idx) = (*slot, *idx);
+                    let $v = move |args: *const i64, slots: *mut i64| unsafe {
+                        *slots.add(slot) + *args.add(idx)
+                    };
+                    $body
+                }
+                (Val::Slot(x), Val::Slot(y)) => {
+                    let (x, y) = (*x, *y);
+                    let $v = move |_: *const i64, slots: *mut i64| unsafe {
+                        *slots.add(x) + *slots.add(y)
+                    };
+                    $body
+                }
+                _ => {
+                    let f = $val.compile();
+                    let $v = move |args: *const i64, slots: *mut i64| f(args, slots);
+                    $body
+                }
+            },
+            _ => {
+                let f = $val.compile();
+                let $v = move |args: *const i64, slots: *mut i64| f(args, slots);
+                $body
+            }
+        }
+    };
+}
+
+// The effects of a trace, once its stack traffic has been resolved
+enum Step {
+    Store(usize, Val),
+    // Continue if `cond > 0` is `positive`, otherwise take the side exit
+    Guard {
+        cond: Val,
+        positive: bool,
+        exit: usize,
+    },
+}
+
+impl Trace {
+    // Compile the ops recorded for one iteration of a loop, starting at its header with `locals` locals live. `recorded`
+    // holds the `ip` of each op along with the `ip` that followed it.
+    fn compile(code: &[Op], recorded: &[(usize, usize)], locals: usize, max_locals: usize) -> Self {
+        let mut stack = Vec::new();
+        let mut depth = locals;
+        let mut slots = max_locals;
+        let mut steps = Vec::new();
+        let mut exits = Vec::new();
+
+        // Store any value on the stack that reads `slot` in a temporary, since `slot` is about to change
+        let mut spill = |stack: &mut Vec<Val>, steps: &mut Vec<Step>, slot: usize| {
+            for val in stack.iter_mut().filter(|val| val.reads(slot)) {
+                steps.push(Step::Store(slots, val.clone()));
+                *val = Val::Slot(slots);
+                slots += 1;
+            }
+        };
+
+        for &(ip, next) in recorded {
+            match code[ip] {
+                Op::Litr(x) => stack.push(Val::Litr(x)),
+                Op::Arg(idx) => stack.push(Val::Arg(idx)),
+                Op::Get(local) => stack.push(Val::Slot(depth - local - 1)),
+                Op::Add => {
+                    let y = stack.pop().unwrap();
+                    let x = stack.pop().unwrap();
+                    stack.push(Val::Add(Box::new(x), Box::new(y)));
+                }
+                Op::PushLocal => {
+                    let val = stack.pop().unwrap();
+                    steps.push(Step::Store(depth, val));
+                    depth += 1;
+                }
+                Op::PopLocal => {
+                    depth -= 1;
+                    spill(&mut stack, &mut steps, depth);
+                }
+                Op::SetLocal(local) => {
+                    let val = stack.pop().unwrap();
+                    let slot = depth - local - 1;
+                    spill(&mut stack, &mut steps, slot);
+                    steps.push(Step::Store(slot, val));
+                }
+                Op::Pop => {
+                    stack.pop().unwrap();
+                }
+                Op::JmpZN(goto) => {
+                    let cond = stack.pop().unwrap();
+                    let taken = next == goto;
+                    steps.push(Step::Guard {
+                        cond,
+                        positive: !taken,
+                        exit: exits.len(),
+                    });
+                    exits.push(SideExit {
+                        ip: if taken { ip + 1 } else { goto },
+                        locals: depth,
+                        stack: stack.clone(),
+                    });
+                }
+                // The back-edge, which ends the trace
+                Op::Jmp(_) => {}
+                Op::Ret => unreachable!("return in trace"),
+            }
+        }
+
+        let code = steps
+            .iter()
+            .rev()
+            .fold(make_func(|_, _, _| LOOP), |cont, step| match step {
+                Step::Store(slot, val) => {
+                    let slot = *slot;
+                    with_val!(val, |v| make_func(move |args, slots, _| {
+                        unsafe {
+                            *slots.add(slot) = v(args, slots);
+                        }
+                        cont.invoke(args, slots, 0)
+                    }))
+                }
+                Step::Guard {
+                    cond,
+                    positive,
+                    exit,
+                } => {
+                    let (positive, exit) = (*positive, *exit as i64);
+                    with_val!(cond, |v| make_func(move |args, slots, _| {
+                        if (v(args, slots) > 0) == positive {
+                            cont.invoke(args, slots, 0)
+                        } else {
+                            exit
+                        }
+                    }))
+                }
+            });
+
+        Self {
+            code,
+            exits,
+            max_slots: slots,
+        }
+    }
+
+    // Run the trace until a guard fails, returning the `ip` to resume the interpreter at
+    unsafe fn run(&self, args: &[i64], stack: &mut Vec<i64>, locals: &mut Vec<i64>) -> usize {
+        locals.reserve(self.max_slots - locals.len());
+        let slots = locals.as_mut_ptr();
+        let exit = loop {
+            let exit = self.code.invoke(args.as_ptr(), slots, 0);
+            if exit != LOOP {
+                break &self.exits[exit as usize];
+            }
+        };
+        locals.set_len(exit.locals);
+        for val in &exit.stack {
+            stack.push(val.eval(args.as_ptr(), slots));
+        }
+        exit.ip
+    }
+}
+
+impl TracingBytecode {
+    // Interpret one iteration of the loop with its header at `header` and its back-edge at `latch`, recording the ops
+    // executed. Returns the `ip` that the interpreter should resume at, and the program's result if it finished.
+    unsafe fn record(
+        prog: &TracingProgram,
+        header: usize,
+        latch: usize,
+        args: &[i64],
+        stack: &mut Vec<i64>,
+        locals: &mut Vec<i64>,
+    ) -> (usize, Option<i64>) {
+        let lp = &prog.loops[header];
+        let code = &prog.bytecode.code;
+        let depth = locals.len();
+        let mut recorded = Vec::new();
+        let mut ip = header;
+        loop {
+            let op = code.get_unchecked(ip);
+            let at = ip;
+            ip += 1;
+            if let Some(res) = Bytecode::step(op, &mut ip, args, stack, locals) {
+                return (ip, Some(res));
+            }
+            recorded.push((at, ip));
+            match op {
+                Op::Jmp(_) if at == latch => break,
+                // Another loop's back-edge
+                Op::Jmp(_) => {
+                    let _ = lp.trace.set(None);
+                    return (ip, None);
+                }
+                // The loop exited, so try again once it's taken another `HOT` back-edges
+                _ if ip < header || ip > latch => {
+                    lp.hits.store(0, Ordering::Relaxed);
+                    return (ip, None);
+                }
+                _ if recorded.len() > MAX_TRACE => {
+                    let _ = lp.trace.set(None);
+                    return (ip, None);
+                }
+                _ => {}
+            }
+        }
+
+        let trace = Trace::compile(code, &recorded, depth, prog.bytecode.max_locals);
+        // Another thread may have got here first, in which case its trace is just as good
+        let _ = lp.trace.set(Some(trace));
+        (ip, None)
+    }
+}
+
+impl Vm for TracingBytecode {
+    type Program<'a> = TracingProgram;
+    type Context = (Vec<i64>, Vec<i64>); // (stack, locals)
+
+    fn compile(expr: &Expr) -> Self::Program<'_> {
+        let bytecode = Bytecode::compile(expr);
+        let loops = bytecode.code.iter().map(|_| Loop::default()).collect();
+        TracingProgram { bytecode, loops }
+    }
+
+    fn new_context() -> Self::Context {
+        (Vec::new(), Vec::new())
+    }
+
+    unsafe fn execute_in(
+        (stack, locals): &mut Self::Context,
+        prog: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
+        let code = &prog.bytecode.code;
+        let mut ip = 0;
+        stack.clear();
+        locals.clear();
+        stack.reserve(prog.bytecode.max_stack);
+        locals.reserve(prog.bytecode.max_locals);
+        loop {
+            let op = code.get_unchecked(ip);
+            if let Op::Jmp(header) = *op {
+                let lp = prog.loops.get_unchecked(header);
+                match lp.trace.get() {
+                    Some(Some(trace)) => {
+                        ip = trace.run(args, stack, locals);
+                        continue;
+                    }
+                    Some(None) => {}
+                    None if lp.hits.fetch_add(1, Ordering::Relaxed) + 1 >= HOT => {
+                        let latch = ip;
+                        match Self::record(prog, header, latch, args, stack, locals) {
+                            (_, Some(res)) => break res,
+                            (next, None) => ip = next,
+                        }
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+            ip += 1;
+            if let Some(res) = Bytecode::step(op, &mut ip, args, stack, locals) {
+                break res;
+            }
+        }
+    }
+}
@@ -4,12 +4,21 @@ pub struct Walker;
 
 impl Vm for Walker {
     type Program<'a> = &'a Expr;
+    type Context = Vec<i64>;
 
     fn compile(expr: &Expr) -> Self::Program<'_> {
         expr
     }
 
-    unsafe fn execute(expr: &Self::Program<'_>, args: &[i64]) -> i64 {
+    fn new_context() -> Self::Context {
+        Vec::new()
+    }
+
+    unsafe fn execute_in(
+        locals: &mut Self::Context,
+        expr: &Self::Program<'_>,
+        args: &[i64],
+    ) -> i64 {
         unsafe fn execute_inner(expr: &Expr, args: &[i64], locals: &mut Vec<i64>) -> i64 {
             // A stand-in for expressions that don't return anything
             const UNIT: i64 = 0;
@@ -45,6 +54,7 @@ impl Vm for Walker {
             }
         }
 
-        execute_inner(expr, args, &mut Vec::new())
+        locals.clear();
+        execute_inner(expr, args, locals)
     }
 }
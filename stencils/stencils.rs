@@ -0,0 +1,100 @@
+// Stencils for `CopyAndPatch`, compiled to an object file by `build.rs` (never linked into the crate itself).
+//
+// Each stencil is the code for one `Bytecode` op. The extern symbols below are holes: `build.rs` records every relocation
+// against them, and they get patched once the stencils have been copied into place. Every stencil ends by tail-calling
+// `CONTINUE` (the next stencil) or `JUMP` (a jump target) with `become`, passing the VM state along in argument
+// registers. `become` guarantees a `jmp` rather than a `call`, so the hardware stack doesn't grow as the program runs.
+#![no_std]
+#![feature(explicit_tail_calls)]
+#![allow(incomplete_features)]
+#![allow(non_upper_case_globals)]
+
+extern "C" {
+    // An operand of the op
+    static HOLE_OPERAND: u8;
+    // The stencil of the next op
+    fn CONTINUE(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64;
+    // The stencil of the op being jumped to
+    fn JUMP(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64;
+}
+
+// Patched operands are addresses as far as the compiler is concerned
+#[inline(always)]
+unsafe fn operand() -> isize {
+    core::ptr::addr_of!(HOLE_OPERAND) as isize
+}
+
+// `sp` points one past the top of the stack, `lp` one past the top of the locals
+
+#[no_mangle]
+pub unsafe extern "C" fn stencil_litr(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    sp.write(operand() as i64);
+    become CONTINUE(sp.add(1), lp, args)
+}
+
+// The operand is the byte offset of the argument
+#[no_mangle]
+pub unsafe extern "C" fn stencil_arg(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    sp.write(args.byte_offset(operand()).read());
+    become CONTINUE(sp.add(1), lp, args)
+}
+
+// The operand is the byte offset of the local, relative to `lp`
+#[no_mangle]
+pub unsafe extern "C" fn stencil_get(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    sp.write(lp.byte_offset(operand()).read());
+    become CONTINUE(sp.add(1), lp, args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stencil_add(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    let sp = sp.sub(1);
+    let top = sp.sub(1);
+    top.write(top.read().wrapping_add(sp.read()));
+    become CONTINUE(sp, lp, args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stencil_push_local(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    let sp = sp.sub(1);
+    lp.write(sp.read());
+    become CONTINUE(sp, lp.add(1), args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stencil_pop_local(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    become CONTINUE(sp, lp.sub(1), args)
+}
+
+// The operand is the byte offset of the local, relative to `lp`
+#[no_mangle]
+pub unsafe extern "C" fn stencil_set_local(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    let sp = sp.sub(1);
+    lp.byte_offset(operand()).write(sp.read());
+    become CONTINUE(sp, lp, args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stencil_pop(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    become CONTINUE(sp.sub(1), lp, args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stencil_jmp_zn(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    let sp = sp.sub(1);
+    if sp.read() <= 0 {
+        become JUMP(sp, lp, args)
+    } else {
+        become CONTINUE(sp, lp, args)
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stencil_jmp(sp: *mut i64, lp: *mut i64, args: *const i64) -> i64 {
+    become JUMP(sp, lp, args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stencil_ret(sp: *mut i64, _lp: *mut i64, _args: *const i64) -> i64 {
+    sp.sub(1).read()
+}
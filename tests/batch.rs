@@ -0,0 +1,52 @@
+mod common;
+
+use common::*;
+use vm_perf::*;
+
+fn exprs() -> [Expr; 2] {
+    [
+        // let x = args[0] in x + args[1]
+        *let_(arg(0), add(get(0), arg(1))),
+        // total = total + args[1], `args[0]` times
+        counted_loop(set(1, add(get(1), arg(1)))),
+    ]
+}
+
+#[test]
+fn batches_match_single_executions() {
+    let args = [
+        [0, 3],
+        [5, 7],
+        [-2, 4],
+        [1, -9],
+        [100, 13],
+        [3, 0],
+        [7, 1],
+        [2, 2],
+        [9, -1],
+    ];
+    let args = args.iter().map(|args| &args[..]).collect::<Vec<_>>();
+    for expr in exprs() {
+        for (name, vm) in backends() {
+            let prog = vm.compile_dyn(&expr);
+            let expected = args
+                .iter()
+                .map(|args| unsafe { prog.execute(args) })
+                .collect::<Vec<_>>();
+            let mut out = vec![0; args.len()];
+            unsafe { prog.execute_batch(&args, &mut out) };
+            assert_eq!(out, expected, "{name}");
+        }
+    }
+}
+
+#[test]
+fn empty_batches() {
+    for expr in exprs() {
+        for (_, vm) in backends() {
+            // There's nothing to run, but backends that set up once per batch mustn't trip over that
+            let prog = vm.compile_dyn(&expr);
+            unsafe { prog.execute_batch(&[], &mut []) };
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// Helpers shared by the integration tests. Not every test uses every helper.
+#![allow(dead_code)]
+
+use std::fmt::Debug;
+pub use vm_perf::builder::*;
+use vm_perf::{Expr, Vm, Walker};
+
+/// Check that `V` running `expr` gives the same result as `Walker` running `reference`, for each set of arguments. The
+/// program is only compiled once, so backends that change as they execute (tracing, tiering) are checked as they do.
+pub fn check_against<V: Vm>(
+    expr: &Expr,
+    reference: &Expr,
+    args: impl IntoIterator<Item = impl AsRef<[i64]> + Debug>,
+) {
+    let prog = V::compile(expr);
+    let walker = Walker::compile(reference);
+    for args in args {
+        assert_eq!(
+            unsafe { V::execute(&prog, args.as_ref()) },
+            unsafe { Walker::execute(&walker, args.as_ref()) },
+            "{} {args:?}",
+            std::any::type_name::<V>()
+        );
+    }
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::*;
+use vm_perf::{
+    closure_stack_continuations::{Guarded, Presized},
+    *,
+};
+
+// let x0 = 0; let x1 = 1; ...; <body>
+fn nested_lets(n: usize, body: Box<Expr>) -> Expr {
+    *(0..n)
+        .rev()
+        .fold(body, |then, i| let_(litr(i as i64), then))
+}
+
+// 1 + (1 + (1 + ...))
+fn nested_adds(n: usize) -> Box<Expr> {
+    (0..n).fold(litr(1), |y, _| add(litr(1), y))
+}
+
+const LETS: usize = 200;
+const ADDS: usize = 1100;
+
+// Deep expressions recurse deeply on the hardware stack too, especially in debug builds
+fn with_big_stack(f: impl FnOnce() + Send + 'static) {
+    std::thread::Builder::new()
+        .stack_size(64 << 20)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn many_locals() {
+    // The innermost local is `LETS - 1`, the outermost is `0`
+    let expr = nested_lets(LETS, add(get(0), get(LETS - 1)));
+    assert_eq!(expr.max_locals(), LETS);
+    for (name, vm) in backends() {
+        let prog = vm.compile_dyn(&expr);
+        assert_eq!(unsafe { prog.execute(&[]) }, LETS as i64 - 1, "{name}");
+    }
+}
+
+#[test]
+fn set_deep_local() {
+    let expr = nested_lets(
+        LETS,
+        then(set(LETS - 1, arg(0)), add(get(LETS - 1), get(1))),
+    );
+    for (name, vm) in backends() {
+        let prog = vm.compile_dyn(&expr);
+        assert_eq!(
+            unsafe { prog.execute(&[42]) },
+            42 + LETS as i64 - 2,
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn deep_stack() {
+    with_big_stack(|| {
+        let expr = nested_lets(LETS, nested_adds(ADDS));
+        assert_eq!(expr.max_stack(), ADDS + 1);
+        for (name, vm) in backends() {
+            let prog = vm.compile_dyn(&expr);
+            assert_eq!(unsafe { prog.execute(&[]) }, ADDS as i64 + 1, "{name}");
+        }
+    });
+}
+
+#[test]
+fn guarded_stack_grows() {
+    with_big_stack(|| {
+        type V = ClosureStackContinuations<Guarded>;
+        let expr = nested_lets(LETS, nested_adds(ADDS));
+        let mut prog = V::compile(&expr);
+        // Pretend the depth couldn't be worked out, so every push past the first few has to trip the guard
+        prog.max_stack = 0;
+        let mut ctx = V::new_context();
+        assert_eq!(
+            unsafe { V::execute_in(&mut ctx, &prog, &[]) },
+            ADDS as i64 + 1
+        );
+        assert!(ctx.1.len() > ADDS);
+    });
+}
+
+fn context_grows<V: Vm>() {
+    let small = *let_(litr(3), get(0));
+    let large = nested_lets(LETS, nested_adds(ADDS));
+    let small_prog = V::compile(&small);
+    let large_prog = V::compile(&large);
+    let mut ctx = V::new_context();
+    unsafe {
+        assert_eq!(V::execute_in(&mut ctx, &small_prog, &[]), 3);
+        assert_eq!(V::execute_in(&mut ctx, &large_prog, &[]), ADDS as i64 + 1);
+        assert_eq!(V::execute_in(&mut ctx, &small_prog, &[]), 3);
+    }
+}
+
+#[test]
+fn shared_context() {
+    with_big_stack(|| {
+        context_grows::<Walker>();
+        context_grows::<Bytecode>();
+        context_grows::<StackCachedBytecode>();
+        context_grows::<RegisterBytecode>();
+        context_grows::<ThreadedBytecode>();
+        context_grows::<ReplicatedBytecode>();
+        context_grows::<SuperBytecode>();
+        context_grows::<TracingBytecode>();
+        context_grows::<Ssa<Bytecode>>();
+        context_grows::<Ssa<Closures>>();
+        context_grows::<Closures>();
+        context_grows::<StackClosures>();
+        context_grows::<TapeClosures>();
+        context_grows::<RegisterClosures<2>>();
+        context_grows::<BytecodeClosures>();
+        context_grows::<TapeContinuations>();
+        context_grows::<TapeTailCalls>();
+        context_grows::<ClosureContinuations>();
+        context_grows::<ClosureStackContinuations<Presized>>();
+        context_grows::<ClosureStackContinuations<Guarded>>();
+        context_grows::<ClosureTailCalls>();
+        context_grows::<SimdClosures<8>>();
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        context_grows::<Jit>();
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        context_grows::<CopyAndPatch>();
+        #[cfg(unix)]
+        context_grows::<RustcAot>();
+        #[cfg(unix)]
+        context_grows::<CAot>();
+        context_grows::<Tiered<Walker, ClosureContinuations, 64>>();
+    });
+}
+
+// Frames of more than a page are probed on the way down
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[test]
+fn jit_large_frames() {
+    with_big_stack(|| {
+        for lets in [511, 512, 513, 1024, 5000] {
+            let expr = nested_lets(lets, add(get(0), get(lets - 1)));
+            check_against::<Jit>(&expr, &expr, [[]]);
+        }
+    });
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::*;
+use vm_perf::{induction::InductionLoop, optimize::Options, *};
+
+// total = total + step, `args[0]` times
+fn sum(step: Box<Expr>) -> Expr {
+    counted_loop(set(1, add(get(1), step)))
+}
+
+fn has_loop(expr: &Expr, body: &Expr) -> bool {
+    match expr {
+        Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => false,
+        Expr::Set(_, rhs) => has_loop(rhs, body),
+        Expr::While(_, b) if **b == *body => true,
+        Expr::Add(x, y) | Expr::Let(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+            has_loop(x, body) || has_loop(y, body)
+        }
+    }
+}
+
+#[test]
+fn counted_loops_are_recognised() {
+    let expr = sum(arg(1));
+    let Expr::Let(_, inner) = &expr else {
+        unreachable!()
+    };
+    let Expr::Then(inner, _) = &**inner else {
+        unreachable!()
+    };
+    let Expr::Let(_, lp) = &**inner else {
+        unreachable!()
+    };
+    let lp = InductionLoop::find(lp).unwrap();
+    assert_eq!(lp.counter, 0);
+    assert_eq!(lp.steps, [(1, &Expr::Arg(1))]);
+}
+
+#[test]
+fn closed_form_matches_the_loop() {
+    let expr = sum(add(arg(1), litr(3)));
+    let args = [-5, 0, 1, 2, 7, 64, 10_000]
+        .into_iter()
+        .flat_map(|count| [-9, 0, 1, 123_456].map(|step| [count, step]));
+    check_against::<Walker>(&optimize(&expr), &expr, args);
+}
+
+#[test]
+fn closed_form_handles_huge_counts() {
+    // The loop would take centuries
+    let closed = optimize(&sum(arg(1)));
+    let closed = Walker::compile(&closed);
+    assert_eq!(
+        unsafe { Walker::execute(&closed, &[i64::MAX, 1]) },
+        i64::MAX
+    );
+    assert_eq!(
+        unsafe { Walker::execute(&closed, &[i64::MAX / 3, -3]) },
+        i64::MAX / 3 * -3
+    );
+}
+
+#[test]
+fn non_affine_loops_are_kept() {
+    // The step changes every iteration
+    let body = then(set(1, add(get(1), get(0))), set(0, add(get(0), litr(-1))));
+    let expr = let_(
+        litr(0),
+        let_(arg(0), then(while_(get(0), body.clone()), get(1))),
+    );
+    assert!(has_loop(&optimize(&expr), &body));
+
+    // The counter doesn't count down by one
+    let body = then(set(1, add(get(1), arg(1))), set(0, add(get(0), litr(-2))));
+    let expr = let_(
+        litr(0),
+        let_(arg(0), then(while_(get(0), body.clone()), get(1))),
+    );
+    assert!(has_loop(&optimize(&expr), &body));
+
+    // A local is assigned twice
+    let body = then(
+        set(1, add(get(1), arg(1))),
+        then(set(1, add(get(1), arg(1))), set(0, add(get(0), litr(-1)))),
+    );
+    let expr = let_(
+        litr(0),
+        let_(arg(0), then(while_(get(0), body.clone()), get(1))),
+    );
+    assert!(has_loop(&optimize(&expr), &body));
+}
+
+#[test]
+fn closed_form_loops_can_be_turned_off() {
+    let expr = sum(arg(1));
+    let options = Options {
+        closed_form_loops: false,
+    };
+    assert_eq!(optimize_with(&expr, options), expr);
+    assert_ne!(optimize(&expr), expr);
+}
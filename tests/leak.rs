@@ -0,0 +1,105 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+use vm_perf::{
+    closure_stack_continuations::{Guarded, Presized},
+    *,
+};
+
+// Counts the bytes currently allocated by each thread, so that the test harness can't interfere
+struct Counting;
+
+thread_local! {
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.with(|live| live.set(live.get() + layout.size() as isize));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.with(|live| live.set(live.get() - layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn live() -> isize {
+    LIVE.with(|live| live.get())
+}
+
+fn create_expr() -> Expr {
+    Expr::Let(
+        Box::new(Expr::Litr(0)),
+        Box::new(Expr::Then(
+            Box::new(Expr::Let(
+                Box::new(Expr::Arg(0)),
+                Box::new(Expr::While(
+                    Box::new(Expr::Get(0)),
+                    Box::new(Expr::Then(
+                        Box::new(Expr::Set(
+                            1,
+                            Box::new(Expr::Add(Box::new(Expr::Get(1)), Box::new(Expr::Arg(1)))),
+                        )),
+                        Box::new(Expr::Set(
+                            0,
+                            Box::new(Expr::Add(Box::new(Expr::Get(0)), Box::new(Expr::Litr(-1)))),
+                        )),
+                    )),
+                )),
+            )),
+            Box::new(Expr::Get(0)),
+        )),
+    )
+}
+
+fn no_leaks<V: Vm>() {
+    let expr = create_expr();
+    let before = live();
+    for _ in 0..1000 {
+        let prog = V::compile(&expr);
+        // Enough iterations to run the loop body, and for `TracingBytecode` to trace it
+        assert_eq!(unsafe { V::execute(&prog, &[20, 13]) }, 260);
+    }
+    assert_eq!(live(), before, "{}", std::any::type_name::<V>());
+}
+
+#[test]
+fn compiled_programs_are_freed() {
+    no_leaks::<Walker>();
+    no_leaks::<Bytecode>();
+    no_leaks::<StackCachedBytecode>();
+    no_leaks::<RegisterBytecode>();
+    no_leaks::<ThreadedBytecode>();
+    no_leaks::<ReplicatedBytecode>();
+    no_leaks::<SuperBytecode>();
+    no_leaks::<TracingBytecode>();
+    no_leaks::<Ssa<Bytecode>>();
+    no_leaks::<Ssa<Closures>>();
+    no_leaks::<Closures>();
+    no_leaks::<StackClosures>();
+    no_leaks::<TapeClosures>();
+    no_leaks::<RegisterClosures<2>>();
+    no_leaks::<BytecodeClosures>();
+    no_leaks::<TapeContinuations>();
+    no_leaks::<TapeTailCalls>();
+    no_leaks::<ClosureContinuations>();
+    no_leaks::<ClosureStackContinuations<Presized>>();
+    no_leaks::<ClosureStackContinuations<Guarded>>();
+    no_leaks::<ClosureTailCalls>();
+    no_leaks::<SimdClosures<8>>();
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    no_leaks::<Jit>();
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    no_leaks::<CopyAndPatch>();
+    #[cfg(unix)]
+    no_leaks::<RustcAot>();
+    #[cfg(unix)]
+    no_leaks::<CAot>();
+    no_leaks::<Tiered<Walker, ClosureContinuations, 64>>();
+}
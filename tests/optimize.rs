@@ -0,0 +1,99 @@
+mod common;
+
+use common::*;
+use vm_perf::{optimize::Options, *};
+
+// Optimise a program, checking that it still gives the same results
+fn check(expr: Box<Expr>) -> Expr {
+    check_with(expr, Options::default())
+}
+
+fn check_with(expr: Box<Expr>, options: Options) -> Expr {
+    let optimized = optimize_with(&expr, options);
+    check_against::<Walker>(&optimized, &expr, [[0, 0], [1, 2], [-5, 7], [100, -3]]);
+    optimized
+}
+
+#[test]
+fn constants_are_folded() {
+    assert_eq!(check(add(litr(2), litr(3))), Expr::Litr(5));
+    assert_eq!(
+        check(add(add(litr(2), litr(3)), arg(0))),
+        *add(litr(5), arg(0))
+    );
+}
+
+#[test]
+fn identities_are_removed() {
+    assert_eq!(check(add(arg(0), litr(0))), Expr::Arg(0));
+    assert_eq!(check(add(add(litr(-2), litr(2)), arg(1))), Expr::Arg(1));
+}
+
+#[test]
+fn dead_loops_are_removed() {
+    // let x = args[0] in (while -1 { x = 5 }; x)
+    assert_eq!(
+        check(let_(
+            arg(0),
+            then(while_(litr(-1), set(0, litr(5))), get(0))
+        )),
+        *let_(arg(0), get(0))
+    );
+    // A loop that returns nothing in place of the whole program keeps its place, but not its body
+    assert_eq!(
+        check(while_(litr(0), let_(arg(0), set(0, arg(1))))),
+        *while_(litr(0), litr(0))
+    );
+}
+
+#[test]
+fn pure_thens_are_removed() {
+    assert_eq!(check(then(add(arg(0), arg(1)), arg(1))), Expr::Arg(1));
+    // let x = args[0] in (x = x + 1; x)
+    let expr = let_(arg(0), then(set(0, add(get(0), litr(1))), get(0)));
+    // The loop itself would be replaced with closed-form arithmetic
+    let options = Options {
+        closed_form_loops: false,
+    };
+    assert_eq!(check_with(expr.clone(), options), *expr);
+}
+
+#[test]
+fn unused_lets_are_removed() {
+    // let x = args[0] in (let y = args[1] in x)
+    assert_eq!(
+        check(let_(arg(0), let_(arg(1), get(1)))),
+        *let_(arg(0), get(0))
+    );
+    // let x = args[0] in (let y = (x = 5; 1) in (y = x; x)), where only the effects on `x` are kept
+    assert_eq!(
+        check(let_(
+            arg(0),
+            let_(then(set(0, litr(5)), litr(1)), then(set(0, get(1)), get(1)))
+        )),
+        *let_(arg(0), then(then(set(0, litr(5)), litr(1)), get(0)))
+    );
+}
+
+#[test]
+fn optimal_programs_are_unchanged() {
+    // let total = 0 in (let n = args[0] in (while n { total = total + args[1]; n = n + -1 }); total)
+    let expr = let_(
+        litr(0),
+        then(
+            let_(
+                arg(0),
+                while_(
+                    get(0),
+                    then(set(1, add(get(1), arg(1))), set(0, add(get(0), litr(-1)))),
+                ),
+            ),
+            get(0),
+        ),
+    );
+    // The loop itself would be replaced with closed-form arithmetic
+    let options = Options {
+        closed_form_loops: false,
+    };
+    assert_eq!(check_with(expr.clone(), options), *expr);
+}
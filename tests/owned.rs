@@ -0,0 +1,59 @@
+mod common;
+
+use common::*;
+use vm_perf::{
+    closure_stack_continuations::{Guarded, Presized},
+    *,
+};
+
+fn create_expr() -> Expr {
+    // let x = args[0]; x + 1
+    *let_(arg(0), add(get(0), litr(1)))
+}
+
+// Only compiles if the program type doesn't borrow from the `Expr`
+fn outlives_ast<V, P>()
+where
+    for<'a> V: Vm<Program<'a> = P>,
+    P: 'static,
+{
+    let prog = {
+        let expr = create_expr();
+        V::compile(&expr)
+    };
+    assert_eq!(unsafe { V::execute(&prog, &[41]) }, 42);
+}
+
+#[test]
+fn programs_are_owned() {
+    outlives_ast::<Bytecode, _>();
+    outlives_ast::<StackCachedBytecode, _>();
+    outlives_ast::<RegisterBytecode, _>();
+    outlives_ast::<ThreadedBytecode, _>();
+    outlives_ast::<ReplicatedBytecode, _>();
+    outlives_ast::<SuperBytecode, _>();
+    outlives_ast::<TracingBytecode, _>();
+    outlives_ast::<Ssa<Bytecode>, _>();
+    outlives_ast::<Ssa<Closures>, _>();
+    outlives_ast::<Closures, _>();
+    outlives_ast::<StackClosures, _>();
+    outlives_ast::<TapeClosures, _>();
+    outlives_ast::<RegisterClosures<2>, _>();
+    outlives_ast::<BytecodeClosures, _>();
+    outlives_ast::<TapeContinuations, _>();
+    outlives_ast::<TapeTailCalls, _>();
+    outlives_ast::<ClosureContinuations, _>();
+    outlives_ast::<ClosureStackContinuations<Presized>, _>();
+    outlives_ast::<ClosureStackContinuations<Guarded>, _>();
+    outlives_ast::<ClosureTailCalls, _>();
+    outlives_ast::<SimdClosures<8>, _>();
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    outlives_ast::<Jit, _>();
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    outlives_ast::<CopyAndPatch, _>();
+    #[cfg(unix)]
+    outlives_ast::<RustcAot, _>();
+    #[cfg(unix)]
+    outlives_ast::<CAot, _>();
+    outlives_ast::<Tiered<Walker, ClosureContinuations, 64>, _>();
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::*;
+use vm_perf::*;
+
+// let a = args[0] in (let b = 1 in (let c = 2 in (while a { a = a + -1 }; b + c)))
+fn expr() -> Box<Expr> {
+    let_(
+        arg(0),
+        let_(
+            litr(1),
+            let_(
+                litr(2),
+                then(
+                    while_(get(2), set(2, add(get(2), litr(-1)))),
+                    add(get(1), get(0)),
+                ),
+            ),
+        ),
+    )
+}
+
+#[test]
+fn locals_used_in_loops_get_registers() {
+    // `a` is declared first, but it's the only one used in the loop
+    assert_eq!(
+        RegisterClosures::<1>::registers(&expr()),
+        [Some(0), None, None]
+    );
+    assert_eq!(
+        RegisterClosures::<2>::registers(&expr()),
+        [Some(0), Some(1), None]
+    );
+    assert_eq!(
+        RegisterClosures::<0>::registers(&expr()),
+        [None, None, None]
+    );
+    assert_eq!(
+        RegisterClosures::<8>::registers(&expr()),
+        [Some(0), Some(1), Some(2)]
+    );
+}
+
+#[test]
+fn any_number_of_registers_works() {
+    fn check<const N: usize>() {
+        // Sibling and nested lets share slots
+        let expr = let_(
+            arg(0),
+            then(
+                let_(arg(1), set(1, add(get(1), get(0)))),
+                let_(
+                    litr(3),
+                    let_(add(get(1), get(0)), then(expr(), add(get(0), get(2)))),
+                ),
+            ),
+        );
+        check_against::<RegisterClosures<N>>(&expr, &expr, [[0, 0], [5, 7], [-3, 100]]);
+    }
+    check::<0>();
+    check::<1>();
+    check::<2>();
+    check::<3>();
+    check::<4>();
+    check::<8>();
+}
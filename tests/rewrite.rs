@@ -0,0 +1,85 @@
+mod common;
+
+use common::*;
+use vm_perf::{rewrite::Rewrite, *};
+
+// The rules that match anywhere in an expression
+fn rules(expr: &Expr) -> Vec<&'static str> {
+    let mut found = Rewrite::find(expr)
+        .map(|rewrite| rewrite.name())
+        .into_iter()
+        .collect::<Vec<_>>();
+    match expr {
+        Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => {}
+        Expr::Set(_, x) => found.extend(rules(x)),
+        Expr::Add(x, y) | Expr::Let(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+            found.extend(rules(x));
+            found.extend(rules(y));
+        }
+    }
+    found
+}
+
+// A program that exercises each rule
+fn examples() -> Vec<(&'static str, Expr)> {
+    vec![
+        ("AddOne", *add(arg(0), litr(1))),
+        ("SubOne", *add(arg(0), litr(-1))),
+        ("AddLitr", *add(arg(0), litr(13))),
+        ("AddArg1", *add(arg(0), arg(1))),
+        (
+            "AddAssign",
+            *let_(arg(0), then(set(0, add(get(0), arg(1))), get(0))),
+        ),
+    ]
+}
+
+fn check<V: Vm>(expr: &Expr) {
+    check_against::<V>(expr, expr, [[0, 0], [1, 2], [-5, 7], [100, -3]]);
+}
+
+#[test]
+fn every_rule_has_an_example() {
+    let examples = examples();
+    for (rule, expr) in &examples {
+        assert!(rules(expr).contains(rule), "{rule}");
+    }
+    let names = examples.iter().map(|(rule, _)| *rule).collect::<Vec<_>>();
+    assert_eq!(names, Rewrite::RULES);
+}
+
+#[test]
+fn backends_agree_on_every_rule() {
+    for (_, expr) in examples() {
+        check::<Closures>(&expr);
+        check::<ClosureContinuations>(&expr);
+        check::<TapeContinuations>(&expr);
+    }
+}
+
+#[test]
+fn add_assign_reads_the_local_first() {
+    // let x = args[0] in (x = x + (x = 5; 1); x)
+    // Updating `x` in place would see the inner assignment, so this is left to the general case
+    let expr = *let_(
+        arg(0),
+        then(set(0, add(get(0), then(set(0, litr(5)), litr(1)))), get(0)),
+    );
+    assert!(!rules(&expr).contains(&"AddAssign"));
+    check::<Closures>(&expr);
+    check::<ClosureContinuations>(&expr);
+    check::<TapeContinuations>(&expr);
+}
+
+#[test]
+fn add_assign_only_matches_the_same_local() {
+    // let x = args[0] in (let y = args[1] in (x = y + 1; x))
+    let expr = *let_(
+        arg(0),
+        let_(arg(1), then(set(1, add(get(0), litr(1))), get(1))),
+    );
+    assert_eq!(rules(&expr), ["AddOne"]);
+    check::<Closures>(&expr);
+    check::<ClosureContinuations>(&expr);
+    check::<TapeContinuations>(&expr);
+}
@@ -0,0 +1,81 @@
+#![feature(portable_simd)]
+
+mod common;
+
+use common::*;
+use vm_perf::{simd_closures::Lanes, *};
+
+// total = total + args[1], `args[0]` times, with an inner loop so lanes also differ in how often the inner loop runs
+fn expr() -> Expr {
+    let inner = let_(
+        get(0),
+        then(
+            while_(
+                get(0),
+                then(set(2, add(get(2), litr(1))), set(0, add(get(0), litr(-1)))),
+            ),
+            set(2, add(get(2), arg(1))),
+        ),
+    );
+    counted_loop(inner)
+}
+
+// Argument sets whose loop counts differ from lane to lane, including ones that never enter the loop
+fn arg_sets(len: usize) -> Vec<[i64; 2]> {
+    const COUNTS: [i64; 7] = [3, 0, -4, 1, 17, -1, 6];
+    (0..len)
+        .map(|i| [COUNTS[i % COUNTS.len()], i as i64 - 5])
+        .collect()
+}
+
+fn walker_results(expr: &Expr, sets: &[[i64; 2]]) -> Vec<i64> {
+    let prog = Walker::compile(expr);
+    sets.iter()
+        .map(|args| unsafe { Walker::execute(&prog, args) })
+        .collect()
+}
+
+fn lanes_match_walker<const N: usize>() {
+    let expr = expr();
+    let prog = SimdClosures::<N>::compile(&expr);
+    let sets = arg_sets(N);
+    let args = [0, 1].map(|i| Lanes::<N>::from_array(core::array::from_fn(|lane| sets[lane][i])));
+    let res = unsafe { SimdClosures::<N>::execute_lanes(&prog, &args) };
+    assert_eq!(
+        res.to_array().as_slice(),
+        walker_results(&expr, &sets),
+        "{N} lanes"
+    );
+}
+
+fn partial_batches_match_walker<const N: usize>() {
+    let expr = expr();
+    let prog = SimdClosures::<N>::compile(&expr);
+    // One context for every batch, as a caller running many batches would
+    let mut ctx = SimdClosures::<N>::new_context();
+    for len in [N - 1, N + 1, 2 * N + 3] {
+        let sets = arg_sets(len);
+        let args = sets.iter().map(|args| &args[..]).collect::<Vec<_>>();
+        let mut out = vec![i64::MIN; len];
+        unsafe { SimdClosures::<N>::execute_batch_in(&mut ctx, &prog, &args, &mut out) };
+        assert_eq!(
+            out,
+            walker_results(&expr, &sets),
+            "{N} lanes, batch of {len}"
+        );
+    }
+}
+
+#[test]
+fn lanes_with_different_trip_counts() {
+    lanes_match_walker::<4>();
+    lanes_match_walker::<8>();
+    lanes_match_walker::<16>();
+}
+
+#[test]
+fn batches_that_dont_fill_the_lanes() {
+    partial_batches_match_walker::<4>();
+    partial_batches_match_walker::<8>();
+    partial_batches_match_walker::<16>();
+}
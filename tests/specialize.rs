@@ -0,0 +1,82 @@
+mod common;
+
+use common::*;
+use vm_perf::*;
+
+// Count the nodes of an expression that match
+fn count(expr: &Expr, f: &impl Fn(&Expr) -> bool) -> usize {
+    let children = match expr {
+        Expr::Litr(_) | Expr::Arg(_) | Expr::Get(_) => 0,
+        Expr::Set(_, x) => count(x, f),
+        Expr::Add(x, y) | Expr::Let(x, y) | Expr::While(x, y) | Expr::Then(x, y) => {
+            count(x, f) + count(y, f)
+        }
+    };
+    children + f(expr) as usize
+}
+
+// Loops that can run, as opposed to the placeholder left behind by unrolling
+fn loops(expr: &Expr) -> usize {
+    count(
+        expr,
+        &|expr| matches!(expr, Expr::While(pred, _) if !matches!(**pred, Expr::Litr(_))),
+    )
+}
+
+fn args(expr: &Expr) -> usize {
+    count(expr, &|expr| matches!(expr, Expr::Arg(_)))
+}
+
+// Check the specialised program against the original, for arguments that match the known ones
+fn check(expr: &Expr, specialized: &Expr, args: impl IntoIterator<Item = [i64; 2]>) {
+    check_against::<Walker>(specialized, expr, args);
+}
+
+#[test]
+fn known_trip_count_is_unrolled() {
+    // total = total + args[1]
+    let expr = counted_loop(set(1, add(get(1), arg(1))));
+    let specialized = specialize(&expr, &[Some(3), None]);
+    assert_eq!(loops(&specialized), 0);
+    check(&expr, &specialized, [[3, 0], [3, 13], [3, -7]]);
+}
+
+#[test]
+fn known_arguments_are_propagated() {
+    // let step = args[1] + 1 in (total = total + step)
+    let expr = counted_loop(let_(add(arg(1), litr(1)), set(2, add(get(2), get(0)))));
+    let specialized = specialize(&expr, &[None, Some(12)]);
+    assert_eq!(loops(&specialized), 1);
+    assert_eq!(args(&specialized), 1);
+    check(&expr, &specialized, [[0, 12], [1, 12], [100, 12], [-5, 12]]);
+}
+
+#[test]
+fn long_loops_are_kept() {
+    let expr = counted_loop(set(1, add(get(1), arg(1))));
+    let specialized = specialize(&expr, &[Some(1000), Some(13)]);
+    assert_eq!(loops(&specialized), 1);
+    assert_eq!(args(&specialized), 0);
+    check(&expr, &specialized, [[1000, 13]]);
+}
+
+#[test]
+fn assignments_in_loops_are_unknown() {
+    // let m = 5 in (let k = args[1] in (while k > 0 { m = m + 1; k = k + -1 }); total = total + m)
+    // `m` starts out known, but the inner loop can't be unrolled, so it's unknown afterwards
+    let expr = counted_loop(let_(
+        litr(5),
+        then(
+            let_(
+                arg(1),
+                while_(
+                    get(0),
+                    then(set(1, add(get(1), litr(1))), set(0, add(get(0), litr(-1)))),
+                ),
+            ),
+            set(2, add(get(2), get(0))),
+        ),
+    ));
+    let specialized = specialize(&expr, &[Some(2), None]);
+    check(&expr, &specialized, [[2, 0], [2, 1], [2, 10]]);
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use common::*;
+use vm_perf::{
+    ssa::{Block, FromIr, Inst, Ir, Term},
+    *,
+};
+
+#[test]
+fn loop_carried_locals_get_phis() {
+    // total = total + args[1]
+    let ir = Ir::new(&counted_loop(set(1, add(get(1), arg(1)))));
+    assert_eq!(
+        ir.to_string(),
+        "\
+b0:
+  v0 = 0
+  v1 = arg 0
+  jmp b1
+b1:
+  v2 = phi [b0: v0], [b2: v5]
+  v3 = phi [b0: v1], [b2: v8]
+  br v3, b2, b3
+b2:
+  v4 = arg 1
+  v5 = v2 + v4
+  v6 = 0
+  v7 = -1
+  v8 = v3 + v7
+  v9 = 0
+  jmp b1
+b3:
+  v10 = 0
+  ret v2
+"
+    );
+}
+
+#[test]
+fn unchanged_locals_have_no_phis() {
+    // let m = args[1]; while m > 0 { total = total + m; m = m + -1 }
+    // The inner loop never changes `n`, so only `total` and `m` need phis there
+    let ir = Ir::new(&counted_loop(let_(
+        arg(1),
+        while_(
+            get(0),
+            then(set(2, add(get(2), get(0))), set(0, add(get(0), litr(-1)))),
+        ),
+    )));
+    let phis = |block| ir.phis(block).count();
+    assert_eq!(
+        (0..ir.blocks.len()).map(phis).collect::<Vec<_>>(),
+        [0, 2, 0, 0, 2, 0, 0]
+    );
+}
+
+fn check_ssa(expr: &Expr, args: &[[i64; 3]]) {
+    check_against::<Ssa<Bytecode>>(expr, expr, args);
+    check_against::<Ssa<Closures>>(expr, expr, args);
+}
+
+const ARGS: [[i64; 3]; 6] = [
+    [0, 0, 0],
+    [1, 2, 3],
+    [5, -3, 2],
+    [-2, 7, 4],
+    [9, 4, 1],
+    [3, 1, 8],
+];
+
+#[test]
+fn nested_loops_run() {
+    // while n > 0 { let m = n; while m > 0 { total = total + m + args[1]; m = m + -1 }; n = n + -1 }
+    let expr = counted_loop(let_(
+        get(0),
+        while_(
+            get(0),
+            then(
+                set(2, add(get(2), add(get(0), arg(1)))),
+                set(0, add(get(0), litr(-1))),
+            ),
+        ),
+    ));
+    check_ssa(&expr, &ARGS);
+}
+
+#[test]
+fn shadowed_lets_run() {
+    // let x = args[0]; let x = x + 1; x = x + args[1]; (let x = x + x; x) + x
+    let expr = let_(
+        arg(0),
+        let_(
+            add(get(0), litr(1)),
+            then(
+                set(0, add(get(0), arg(1))),
+                add(let_(add(get(0), get(1)), get(0)), get(0)),
+            ),
+        ),
+    );
+    check_ssa(&expr, &ARGS);
+}
+
+#[test]
+fn swaps_in_loops_run() {
+    // let a = args[0]; let b = args[1]; let n = args[2]; while n > 0 { let t = a; a = b; b = t; n = n + -1 }; a + b + b
+    let body = let_(
+        get(2),
+        then(
+            set(3, get(2)),
+            then(set(2, get(0)), set(1, add(get(1), litr(-1)))),
+        ),
+    );
+    let expr = let_(
+        arg(0),
+        let_(
+            arg(1),
+            let_(
+                arg(2),
+                then(while_(get(0), body), add(add(get(2), get(1)), get(1))),
+            ),
+        ),
+    );
+    check_ssa(&expr, &ARGS);
+}
+
+#[test]
+fn branches_to_blocks_with_phis() {
+    // b0 falls through to b1 when `args[0]` is positive, and b2 needs a phi move when it's reached from b0. Lowering
+    // can't put those moves between b0 and b1, where the fall-through path would run them too.
+    let ir = Ir {
+        insts: vec![
+            Inst::Arg(0),
+            Inst::Litr(1),
+            Inst::Litr(2),
+            Inst::Phi(vec![(0, 2), (1, 1)]),
+        ],
+        blocks: vec![
+            Block {
+                insts: vec![0, 1, 2],
+                term: Term::Branch(0, 1, 2),
+            },
+            Block {
+                insts: vec![],
+                term: Term::Jmp(2),
+            },
+            Block {
+                insts: vec![3],
+                term: Term::Ret(3),
+            },
+        ],
+    };
+    for (args, res) in [([1], 1), ([0], 2)] {
+        let prog = Bytecode::compile_ir(&ir);
+        assert_eq!(
+            unsafe { Bytecode::execute(&prog, &args) },
+            res,
+            "Bytecode {args:?}"
+        );
+        let prog = Closures::compile_ir(&ir);
+        assert_eq!(
+            unsafe { Closures::execute(&prog, &args) },
+            res,
+            "Closures {args:?}"
+        );
+    }
+}
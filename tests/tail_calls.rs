@@ -0,0 +1,46 @@
+use vm_perf::*;
+
+// let total = 0; let count = args[0]; while count > 0 { total = total + 1; count = count + -1 }; total
+fn count_up() -> Expr {
+    Expr::Let(
+        Box::new(Expr::Litr(0)),
+        Box::new(Expr::Then(
+            Box::new(Expr::Let(
+                Box::new(Expr::Arg(0)),
+                Box::new(Expr::While(
+                    Box::new(Expr::Get(0)),
+                    Box::new(Expr::Then(
+                        Box::new(Expr::Set(
+                            1,
+                            Box::new(Expr::Add(Box::new(Expr::Get(1)), Box::new(Expr::Litr(1)))),
+                        )),
+                        Box::new(Expr::Set(
+                            0,
+                            Box::new(Expr::Add(Box::new(Expr::Get(0)), Box::new(Expr::Litr(-1)))),
+                        )),
+                    )),
+                )),
+            )),
+            Box::new(Expr::Get(0)),
+        )),
+    )
+}
+
+// Enough iterations to overflow the native stack many times over if any op were an ordinary call, even in debug builds
+const ITERATIONS: i64 = 100_000_000;
+
+fn long_loop<V: Vm>() {
+    let expr = count_up();
+    let prog = V::compile(&expr);
+    assert_eq!(unsafe { V::execute(&prog, &[ITERATIONS]) }, ITERATIONS);
+}
+
+#[test]
+fn tape_tail_calls_long_loop() {
+    long_loop::<TapeTailCalls>();
+}
+
+#[test]
+fn closure_tail_calls_long_loop() {
+    long_loop::<ClosureTailCalls>();
+}
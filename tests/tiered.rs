@@ -0,0 +1,101 @@
+mod common;
+
+use common::*;
+use std::time::{Duration, Instant};
+use vm_perf::*;
+
+// let x = args[0]; x + 1
+fn create_expr() -> Expr {
+    *let_(arg(0), add(get(0), litr(1)))
+}
+
+type Tiers = Tiered<Walker, ClosureContinuations, 8>;
+
+#[test]
+fn cold_programs_stay_cold() {
+    let expr = create_expr();
+    let prog = Tiers::compile(&expr);
+    for i in 0..7 {
+        assert_eq!(unsafe { Tiers::execute(&prog, &[i]) }, i + 1);
+    }
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(!prog.is_promoted());
+}
+
+#[test]
+fn hot_programs_get_promoted() {
+    let expr = create_expr();
+    let prog = Tiers::compile(&expr);
+    let mut ctx = Tiers::new_context();
+    let start = Instant::now();
+    let mut i = 0;
+    // Results are the same whichever tier runs them, including while the background compile is in flight
+    while !prog.is_promoted() {
+        assert_eq!(unsafe { Tiers::execute_in(&mut ctx, &prog, &[i]) }, i + 1);
+        assert!(start.elapsed() < Duration::from_secs(10), "never promoted");
+        i += 1;
+    }
+    assert!(i >= 8);
+    assert_eq!(unsafe { Tiers::execute_in(&mut ctx, &prog, &[41]) }, 42);
+}
+
+#[test]
+fn results_match_across_the_promotion() {
+    // total = total + args[1], `args[0]` times
+    let expr = counted_loop(set(1, add(get(1), arg(1))));
+    check_against::<Tiers>(&expr, &expr, (0..100).map(|i| [i % 7, i - 50]));
+}
+
+// A backend that can't compile anything
+struct Broken;
+
+impl Vm for Broken {
+    type Program<'a> = ();
+    type Context = ();
+
+    fn compile(_: &Expr) -> Self::Program<'_> {
+        panic!("can't compile");
+    }
+
+    fn new_context() -> Self::Context {}
+
+    unsafe fn execute_in(_: &mut Self::Context, _: &Self::Program<'_>, _: &[i64]) -> i64 {
+        unreachable!()
+    }
+}
+
+#[test]
+fn failed_promotions_stay_cold() {
+    type BrokenTiers = Tiered<Walker, Broken, 8>;
+    let expr = create_expr();
+    let prog = BrokenTiers::compile(&expr);
+    for i in 0..100 {
+        assert_eq!(unsafe { BrokenTiers::execute(&prog, &[i]) }, i + 1);
+    }
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(!prog.is_promoted());
+
+    // The compiler thread survives the panic, so other programs still get promoted
+    let prog = Tiers::compile(&expr);
+    let start = Instant::now();
+    let mut i = 0;
+    while !prog.is_promoted() {
+        assert_eq!(unsafe { Tiers::execute(&prog, &[i]) }, i + 1);
+        assert!(start.elapsed() < Duration::from_secs(10), "never promoted");
+        i += 1;
+    }
+}
+
+#[test]
+fn a_threshold_of_one_promotes_straight_away() {
+    type Eager = Tiered<Walker, ClosureContinuations, 1>;
+    let expr = create_expr();
+    let prog = Eager::compile(&expr);
+    assert_eq!(unsafe { Eager::execute(&prog, &[1]) }, 2);
+    let start = Instant::now();
+    while !prog.is_promoted() {
+        assert!(start.elapsed() < Duration::from_secs(10), "never promoted");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(unsafe { Eager::execute(&prog, &[41]) }, 42);
+}
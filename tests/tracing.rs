@@ -0,0 +1,60 @@
+mod common;
+
+use common::*;
+use vm_perf::*;
+
+// Run a program enough times, with enough iterations, for its loops to be traced
+fn check(expr: &Expr) {
+    let args = [[0, 3], [1, 3], [100, 3], [1000, -7], [2, 3], [1000, 5]];
+    check_against::<TracingBytecode>(expr, expr, args);
+}
+
+#[test]
+fn sum() {
+    // total = total + args[1]
+    check(&counted_loop(set(1, add(get(1), arg(1)))));
+}
+
+#[test]
+fn locals_in_loop() {
+    // total = total + (let x = n + args[1] in (let y = x in (x = 1; y + x)))
+    check(&counted_loop(set(
+        1,
+        add(
+            get(1),
+            let_(
+                add(get(0), arg(1)),
+                let_(get(0), then(set(1, litr(1)), add(get(0), get(1)))),
+            ),
+        ),
+    )));
+}
+
+#[test]
+fn nested_loops() {
+    // let m = args[1]; while m > 0 { total = total + m; m = m + -1 }
+    check(&counted_loop(let_(
+        arg(1),
+        while_(
+            get(0),
+            then(set(2, add(get(2), get(0))), set(0, add(get(0), litr(-1)))),
+        ),
+    )));
+}
+
+#[test]
+fn side_exits() {
+    // Once `total` passes 50, the inner loop starts running, failing the guard recorded while it didn't, with `total`
+    // waiting on the stack:
+    // total = total + (let k = total + -50 in (while k > 0 { k = k + -1 }; 1))
+    check(&counted_loop(set(
+        1,
+        add(
+            get(1),
+            let_(
+                add(get(1), litr(-50)),
+                then(while_(get(0), set(0, add(get(0), litr(-1)))), litr(1)),
+            ),
+        ),
+    )));
+}